@@ -0,0 +1,568 @@
+use crate::keyring::KeyringEntry;
+use crate::subscription_diff::ConfigSnapshot;
+use crate::{
+    fetch_options, fetched_subscription_state, keyring, leaf_callback, leaf_config, policy,
+    subscription_state, util, window_manager,
+};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use leaf_sdk_desktop::SubscriptionState;
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Runtime, Url};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+use tauri_plugin_http::reqwest;
+
+const SCHEME: &str = "leafvpn";
+
+const SIGNATURE_PARAM: &str = "sig";
+const EXPIRY_PARAM: &str = "exp";
+const RATE_LIMIT: usize = 5;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+static RECENT_ACTIONS: Lazy<Mutex<VecDeque<Instant>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+// Signatures of the signed links that were accepted, with their expiry, so
+// that none of them can be replayed before it expires.
+static USED_SIGNATURES: Lazy<Mutex<HashMap<Vec<u8>, u64>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DeepLinkAction {
+    Install { profile: String },
+    Connect,
+    Disconnect,
+    Select { outbound: String },
+    Import { url: String },
+}
+
+impl DeepLinkAction {
+    // The command that does the same as this action, whose policy entry
+    // covers it too.
+    fn command(&self) -> Option<&'static str> {
+        match self {
+            DeepLinkAction::Install { .. } => Some("update_subscription"),
+            DeepLinkAction::Connect => Some("run_leaf"),
+            DeepLinkAction::Disconnect => Some("stop_leaf"),
+            DeepLinkAction::Select { .. } => None,
+            DeepLinkAction::Import { .. } => Some("import_offline_subscription"),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            DeepLinkAction::Install { profile } => {
+                format!("install the subscription profile {}", profile)
+            }
+            DeepLinkAction::Connect => "connect the VPN".to_string(),
+            DeepLinkAction::Disconnect => "disconnect the VPN".to_string(),
+            DeepLinkAction::Select { outbound } => format!("switch to the server {}", outbound),
+            DeepLinkAction::Import { url } => format!("import a subscription file from {}", url),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DeepLinkEvent {
+    pub url: String,
+    pub action: Option<DeepLinkAction>,
+    /// Id of the trusted key that signed the link.
+    pub signed_by: Option<String>,
+    pub error: Option<String>,
+}
+
+fn query(url: &Url, name: &str) -> Result<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| anyhow!("missing '{}' query parameter", name))
+}
+
+fn decode_profile(encoded: &str) -> Result<String> {
+    let bytes = STANDARD
+        .decode(encoded)
+        .or_else(|_| URL_SAFE_NO_PAD.decode(encoded.trim_end_matches('=')))
+        .map_err(|_| anyhow!("base64 decode failed"))?;
+    let profile = String::from_utf8(bytes).map_err(|_| anyhow!("profile is not valid text"))?;
+
+    let profile = profile.trim().to_string();
+    if profile.is_empty() {
+        return Err(anyhow!("decoded profile is empty"));
+    }
+    if profile.len() > util::CLIENT_ID_LEN {
+        return Err(anyhow!("decoded profile is unexpectedly large"));
+    }
+    Ok(profile)
+}
+
+/// Parses a `leafvpn://` link into the action it asks for.
+pub fn parse(link: &str) -> Result<DeepLinkAction> {
+    let url = Url::parse(link.trim())?;
+    if url.scheme() != SCHEME {
+        return Err(anyhow!(
+            "invalid scheme: expected '{}', got '{}'",
+            SCHEME,
+            url.scheme()
+        ));
+    }
+
+    // Links may carry an expiry as unix seconds.
+    if let Ok(expiry) = query(&url, EXPIRY_PARAM) {
+        let expiry: u64 = expiry
+            .parse()
+            .map_err(|_| anyhow!("invalid '{}' query parameter", EXPIRY_PARAM))?;
+        if util::now() > expiry {
+            return Err(anyhow!("the link has expired"));
+        }
+    }
+
+    match url.host_str().unwrap_or_default() {
+        "install" => Ok(DeepLinkAction::Install {
+            profile: decode_profile(&query(&url, "profile")?)?,
+        }),
+        "connect" => Ok(DeepLinkAction::Connect),
+        "disconnect" => Ok(DeepLinkAction::Disconnect),
+        "select" => Ok(DeepLinkAction::Select {
+            outbound: query(&url, "outbound")?,
+        }),
+        "import" => {
+            let target = query(&url, "url")?;
+            if Url::parse(&target)?.scheme() != "https" {
+                return Err(anyhow!("only https subscription files can be imported"));
+            }
+            Ok(DeepLinkAction::Import { url: target })
+        }
+        host => Err(anyhow!("unknown action '{}'", host)),
+    }
+}
+
+/// Checks the optional `sig` parameter, an Ed25519 signature over the link,
+/// exactly as it was received, up to `&sig=`, which therefore has to come
+/// last. The signed part must include `exp`, and each signature is accepted
+/// only once. Returns the id of the trusted key that made it, or `None` for
+/// unsigned links and links signed by an unknown key.
+pub fn verify<R: Runtime>(app: &AppHandle<R>, link: &str) -> Result<Option<String>> {
+    verify_with(&keyring::trusted_keys(app)?, link)
+}
+
+fn verify_with(keys: &[KeyringEntry], link: &str) -> Result<Option<String>> {
+    let link = link.trim();
+    let marker = format!("{}=", SIGNATURE_PARAM);
+    let Some(position) = link
+        .rfind(&format!("&{}", marker))
+        .or_else(|| link.rfind(&format!("?{}", marker)))
+    else {
+        return Ok(None);
+    };
+
+    let (message, encoded) = (&link[..position], &link[position + marker.len() + 1..]);
+    if encoded.contains('&') {
+        return Err(anyhow!("the signature must be the last query parameter"));
+    }
+    let encoded = encoded.trim_end_matches('=');
+    let signature = URL_SAFE_NO_PAD
+        .decode(encoded)
+        .or_else(|_| STANDARD.decode(encoded))
+        .map_err(|_| anyhow!("signature is not valid base64"))?;
+    let signature =
+        Signature::from_slice(&signature).map_err(|_| anyhow!("signature has the wrong length"))?;
+
+    for entry in keys {
+        let Ok(key) = keyring::decode_public_key(&entry.public_key) else {
+            continue;
+        };
+        let Ok(key) = <[u8; 32]>::try_from(key.as_slice()) else {
+            continue;
+        };
+        let Ok(key) = VerifyingKey::from_bytes(&key) else {
+            continue;
+        };
+        if key.verify(message.as_bytes(), &signature).is_ok() {
+            let expiry = signed_expiry(message)?;
+            let mut used = USED_SIGNATURES.lock();
+            mark_used(&mut used, &signature.to_bytes(), expiry, util::now())?;
+            return Ok(Some(entry.id.clone()));
+        }
+    }
+
+    warn!("Deep link is not signed by a trusted key");
+    Ok(None)
+}
+
+/// Adds `exp` and a signature by `key` to `link`, in the form [`verify`]
+/// accepts.
+pub fn sign(link: &str, expiry: u64, key: &SigningKey) -> String {
+    let separator = if link.contains('?') { '&' } else { '?' };
+    let message = format!("{}{}{}={}", link, separator, EXPIRY_PARAM, expiry);
+    let signature = key.sign(message.as_bytes());
+    format!(
+        "{}&{}={}",
+        message,
+        SIGNATURE_PARAM,
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    )
+}
+
+fn signed_expiry(message: &str) -> Result<u64> {
+    let expiry = query(&Url::parse(message)?, EXPIRY_PARAM)
+        .map_err(|_| anyhow!("signed links must carry an '{}' parameter", EXPIRY_PARAM))?;
+    expiry
+        .parse()
+        .map_err(|_| anyhow!("invalid '{}' query parameter", EXPIRY_PARAM))
+}
+
+fn mark_used(
+    used: &mut HashMap<Vec<u8>, u64>,
+    signature: &[u8],
+    expiry: u64,
+    now: u64,
+) -> Result<()> {
+    used.retain(|_, expiry| *expiry >= now);
+    if used.insert(signature.to_vec(), expiry).is_some() {
+        return Err(anyhow!("this signed link was already used"));
+    }
+    Ok(())
+}
+
+// Allows at most `RATE_LIMIT` actions per `RATE_WINDOW`.
+fn check_rate_limit() -> Result<()> {
+    let mut recent = RECENT_ACTIONS.lock();
+    let now = Instant::now();
+    while recent
+        .front()
+        .is_some_and(|time| now.duration_since(*time) > RATE_WINDOW)
+    {
+        recent.pop_front();
+    }
+    if recent.len() >= RATE_LIMIT {
+        return Err(anyhow!("too many deep links, try again in a minute"));
+    }
+    recent.push_back(now);
+    Ok(())
+}
+
+// Asks the user before an unsigned link changes anything.
+fn confirm<R: Runtime>(app: &AppHandle<R>, action: &DeepLinkAction) -> bool {
+    app.dialog()
+        .message(format!(
+            "A link wants to {}. Only continue if you opened it yourself.",
+            action.describe()
+        ))
+        .title("Leaf VPN")
+        .kind(MessageDialogKind::Warning)
+        .buttons(MessageDialogButtons::OkCancelCustom(
+            "Continue".to_string(),
+            "Cancel".to_string(),
+        ))
+        .blocking_show()
+}
+
+fn main_window<R: Runtime>(app: &AppHandle<R>) -> Result<tauri::Window<R>> {
+    window_manager::WindowManager::get_main_window(app)
+        .map(|window| window.as_ref().window())
+        .ok_or_else(|| anyhow!("main window is not available"))
+}
+
+// Picks `outbound` in the first select group that contains it.
+async fn select(outbound: String) -> Result<()> {
+    let api_port = leaf_sdk_desktop::get_preferences()?.api_port;
+    let snapshot = ConfigSnapshot::of(&leaf_config::read()?);
+    let group = snapshot
+        .selects
+        .iter()
+        .find(|(_, members)| members.contains(&outbound))
+        .map(|(group, _)| group.clone())
+        .ok_or_else(|| anyhow!("no select group contains '{}'", outbound))?;
+
+    let url = Url::parse_with_params(
+        &format!("http://127.0.0.1:{}/api/v1/app/outbound/select", api_port),
+        [("outbound", &group), ("select", &outbound)],
+    )?;
+    let response = reqwest::Client::new().post(url.as_str()).send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "selecting '{}' returned {}",
+            outbound,
+            response.status()
+        ));
+    }
+    Ok(())
+}
+
+async fn import<R: Runtime>(app: AppHandle<R>, url: String) -> Result<()> {
+    policy::check_import().map_err(|e| anyhow!(e))?;
+
+    let response = reqwest::Client::new().get(&url).send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("{} returned {}", url, response.status()));
+    }
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+    let path = std::env::temp_dir().join(format!("leafvpn-import-{}.leafsub", stamp));
+    fs::write(&path, response.bytes().await?)?;
+
+    let keyring_json = keyring::trusted_keyring_json(&app)?;
+    let file = path.clone();
+    leaf_sdk_desktop::import_offline_subscription(
+        path.to_string_lossy().to_string(),
+        None,
+        keyring_json,
+        move |state| {
+            if matches!(
+                state,
+                SubscriptionState::SUCCESS | SubscriptionState::ERROR { .. }
+            ) {
+                let _ = fs::remove_file(&file);
+            }
+            subscription_state(&app, state.clone());
+        },
+    );
+    Ok(())
+}
+
+/// Carries out `action` in the backend, unless the policy disables the
+/// command that does the same.
+pub fn run<R: Runtime>(app: &AppHandle<R>, action: DeepLinkAction) -> Result<()> {
+    if let Some(command) = action.command() {
+        policy::check_command(command).map_err(|e| anyhow!(e))?;
+    }
+
+    match action {
+        DeepLinkAction::Install { profile } => {
+            policy::check_client_id(&profile).map_err(|e| anyhow!(e))?;
+            policy::check_update(false).map_err(|e| anyhow!(e))?;
+            let handle = app.clone();
+            fetch_options::update_subscription(app, profile, move |state, options| {
+                fetched_subscription_state(&handle, state, options);
+            })
+        }
+        DeepLinkAction::Connect => {
+            let window = main_window(app)?;
+            leaf_sdk_desktop::run_leaf(move |state| {
+                leaf_callback(window.clone(), state.clone());
+            })
+        }
+        DeepLinkAction::Disconnect => {
+            let window = main_window(app)?;
+            leaf_sdk_desktop::stop_leaf(move |state| {
+                leaf_callback(window.clone(), state.clone());
+            });
+            Ok(())
+        }
+        DeepLinkAction::Select { outbound } => {
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = select(outbound).await {
+                    error!("Deep link select failed: {}", e);
+                }
+            });
+            Ok(())
+        }
+        DeepLinkAction::Import { url } => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = import(app, url).await {
+                    error!("Deep link import failed: {}", e);
+                }
+            });
+            Ok(())
+        }
+    }
+}
+
+fn emit<R: Runtime>(
+    app: &AppHandle<R>,
+    url: &str,
+    action: Option<DeepLinkAction>,
+    signed_by: Option<String>,
+    error: Option<anyhow::Error>,
+) {
+    if let Some(e) = &error {
+        error!("Deep link {} failed: {}", url, e);
+    }
+    let _ = app.emit(
+        "deep-link-event",
+        DeepLinkEvent {
+            url: url.to_string(),
+            action,
+            signed_by,
+            error: error.map(|e| e.to_string()),
+        },
+    );
+}
+
+// Only links that parse and verify count against the rate limit.
+fn check<R: Runtime>(app: &AppHandle<R>, url: &str) -> Result<(DeepLinkAction, Option<String>)> {
+    let action = parse(url)?;
+    let signed_by = verify(app, url)?;
+    check_rate_limit()?;
+    Ok((action, signed_by))
+}
+
+fn handle_url<R: Runtime>(app: &AppHandle<R>, url: &str) {
+    info!("Handling deep link: {}", url);
+
+    let (action, signed_by) = match check(app, url) {
+        Ok(checked) => checked,
+        Err(e) => return emit(app, url, None, None, Some(e)),
+    };
+
+    if signed_by.is_none() && !confirm(app, &action) {
+        warn!("Deep link {} was declined", url);
+        return;
+    }
+
+    emit(app, url, Some(action.clone()), signed_by.clone(), None);
+    if let Err(e) = run(app, action.clone()) {
+        emit(app, url, Some(action), signed_by, Some(e));
+    }
+}
+
+/// Checks and runs every link on a separate thread, as confirmation dialogs
+/// block. Each link is reported as `deep-link-event` before it runs and again
+/// if it fails.
+pub fn handle_urls<R: Runtime>(app: &AppHandle<R>, urls: &[String]) {
+    let app = app.clone();
+    let urls = urls.to_vec();
+    thread::spawn(move || {
+        for url in urls {
+            handle_url(&app, &url);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT_ID: &str = "123e4567-e89b-12d3-a456-426614174000";
+
+    fn install_link(encoded: &str) -> String {
+        format!("leafvpn://install?profile={}", encoded)
+    }
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn keys() -> Vec<KeyringEntry> {
+        let public_key = URL_SAFE_NO_PAD.encode(signing_key().verifying_key().as_bytes());
+        vec![KeyringEntry {
+            id: "team".to_string(),
+            fingerprint: String::new(),
+            public_key,
+            added_at: 0,
+            revoked: false,
+            default: true,
+            builtin: false,
+        }]
+    }
+
+    fn sign(link: &str) -> String {
+        let signature = signing_key().sign(link.as_bytes());
+        format!(
+            "{}&sig={}",
+            link,
+            URL_SAFE_NO_PAD.encode(signature.to_bytes())
+        )
+    }
+
+    #[test]
+    fn rejects_other_schemes_and_unknown_actions() {
+        assert!(parse("https://install?profile=abc").is_err());
+        assert!(parse("leafvpn://uninstall").is_err());
+        assert!(parse("not a link").is_err());
+        assert_eq!(parse("LEAFVPN://connect").unwrap(), DeepLinkAction::Connect);
+    }
+
+    #[test]
+    fn decodes_profiles_in_both_base64_alphabets() {
+        let install = DeepLinkAction::Install {
+            profile: CLIENT_ID.to_string(),
+        };
+        let standard = STANDARD.encode(CLIENT_ID).replace('=', "%3D");
+        assert_eq!(parse(&install_link(&standard)).unwrap(), install);
+        let url_safe = URL_SAFE_NO_PAD.encode(CLIENT_ID);
+        assert_eq!(parse(&install_link(&url_safe)).unwrap(), install);
+        assert!(parse(&install_link("not base64!")).is_err());
+    }
+
+    #[test]
+    fn limits_profiles_to_the_client_id_length() {
+        let longer = format!("{}0", CLIENT_ID);
+        assert!(parse(&install_link(&URL_SAFE_NO_PAD.encode(longer))).is_err());
+        assert!(parse(&install_link(&URL_SAFE_NO_PAD.encode("  "))).is_err());
+    }
+
+    #[test]
+    fn imports_only_over_https() {
+        let link = "leafvpn://import?url=https%3A%2F%2Fexample.com%2Fa.leafsub";
+        assert_eq!(
+            parse(link).unwrap(),
+            DeepLinkAction::Import {
+                url: "https://example.com/a.leafsub".to_string()
+            }
+        );
+        assert!(parse("leafvpn://import?url=http%3A%2F%2Fexample.com%2Fa.leafsub").is_err());
+        assert!(parse("leafvpn://import?url=file%3A%2F%2F%2Fetc%2Fpasswd").is_err());
+    }
+
+    #[test]
+    fn rejects_expired_links() {
+        let link = |exp: u64| format!("leafvpn://connect?exp={}", exp);
+        assert!(parse(&link(util::now() + 60)).is_ok());
+        assert!(parse(&link(util::now() - 60)).is_err());
+        assert!(parse("leafvpn://connect?exp=soon").is_err());
+    }
+
+    #[test]
+    fn verifies_signatures_of_trusted_keys() {
+        let link = format!("leafvpn://select?outbound=tokyo&exp={}", util::now() + 60);
+        assert_eq!(verify_with(&keys(), &link).unwrap(), None);
+        assert_eq!(
+            verify_with(&keys(), &sign(&link)).unwrap().as_deref(),
+            Some("team")
+        );
+
+        let tampered = sign(&link).replace("tokyo", "osaka");
+        assert_eq!(verify_with(&keys(), &tampered).unwrap(), None);
+        assert!(verify_with(&keys(), &format!("{}&exp=1", sign(&link))).is_err());
+    }
+
+    #[test]
+    fn verifies_links_it_signed() {
+        let link = install_link(&URL_SAFE_NO_PAD.encode(CLIENT_ID));
+        let link = super::sign(&link, util::now() + 60, &signing_key());
+        assert_eq!(
+            verify_with(&keys(), &link).unwrap().as_deref(),
+            Some("team")
+        );
+        assert!(parse(&link).is_ok());
+    }
+
+    #[test]
+    fn signed_links_need_an_expiry_and_run_once() {
+        let link = format!("leafvpn://select?outbound=paris&exp={}", util::now() + 60);
+        assert!(verify_with(&keys(), &sign(&link)).is_ok());
+        assert!(verify_with(&keys(), &sign(&link)).is_err());
+        assert!(verify_with(&keys(), &sign("leafvpn://select?outbound=paris")).is_err());
+    }
+
+    #[test]
+    fn forgets_used_signatures_once_they_expire() {
+        let mut used = HashMap::new();
+        assert!(mark_used(&mut used, b"sig", 100, 50).is_ok());
+        assert!(mark_used(&mut used, b"sig", 100, 60).is_err());
+        assert!(mark_used(&mut used, b"sig", 200, 150).is_ok());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::helper::ValidationReport;
+use crate::{persistence, util};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::net::{IpAddr, SocketAddr};
+use tauri::{AppHandle, Runtime};
+
+const DNS_FILE: &str = "dns.json";
+const DNS_KEY: &str = "dns";
+
+/// A plain DNS resolver given as `1.1.1.1` or `udp://1.1.1.1:53`. Leaf has
+/// no DNS over HTTPS or TLS.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DnsUpstream {
+    pub address: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HostOverride {
+    pub domain: String,
+    pub ips: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct DnsSettings {
+    pub upstreams: Vec<DnsUpstream>,
+    pub hosts: Vec<HostOverride>,
+}
+
+impl DnsUpstream {
+    /// Returns the address as leaf expects it, without the `udp://` scheme.
+    pub fn leaf_address(&self) -> Result<&str> {
+        let address = self.address.trim();
+        let rest = match address.split_once("://") {
+            Some(("udp", rest)) => rest,
+            Some(_) => {
+                return Err(anyhow!(
+                    "'{}': leaf only supports plain DNS resolvers",
+                    address
+                ))
+            }
+            None => address,
+        };
+
+        // Plain DNS must not depend on another resolver to be reached.
+        if rest.parse::<IpAddr>().is_err() && rest.parse::<SocketAddr>().is_err() {
+            return Err(anyhow!("'{}' is not a valid resolver address", address));
+        }
+        Ok(rest)
+    }
+}
+
+pub fn validate_settings(settings: &DnsSettings) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    for upstream in &settings.upstreams {
+        if let Err(e) = upstream.leaf_address() {
+            report.error("upstreams", e.to_string());
+        }
+    }
+
+    for (index, host) in settings.hosts.iter().enumerate() {
+        let field = format!("hosts[{}]", index);
+        if !util::is_domain(host.domain.trim()) {
+            report.error(&field, format!("'{}' is not a valid domain", host.domain));
+        }
+        if host.ips.is_empty() {
+            report.error(&field, "at least one address is required");
+        }
+        for ip in &host.ips {
+            if ip.trim().parse::<IpAddr>().is_err() {
+                report.error(&field, format!("'{}' is not an IP address", ip));
+            }
+        }
+    }
+
+    report
+}
+
+pub fn get_settings<R: Runtime>(app: &AppHandle<R>) -> Result<DnsSettings> {
+    persistence::load(app, DNS_FILE, DNS_KEY)
+}
+
+pub fn check_settings(settings: &DnsSettings) -> Result<ValidationReport> {
+    let report = validate_settings(settings);
+    if !report.is_valid() {
+        return Err(anyhow!("invalid DNS settings: {}", report));
+    }
+    Ok(report)
+}
+
+/// Validates `settings` and stores them in place of the current ones.
+pub fn save_settings<R: Runtime>(
+    app: &AppHandle<R>,
+    settings: &DnsSettings,
+) -> Result<ValidationReport> {
+    let report = check_settings(settings)?;
+    persistence::save(app, DNS_FILE, DNS_KEY, settings)?;
+    Ok(report)
+}
+
+pub fn set_settings<R: Runtime>(app: &AppHandle<R>, settings: String) -> Result<ValidationReport> {
+    let settings: DnsSettings = serde_json::from_str(&settings)?;
+    save_settings(app, &settings)
+}
+
+/// Renders the settings as the `dns` object of a leaf JSON config.
+pub fn to_leaf_dns(settings: &DnsSettings) -> Value {
+    let servers: Vec<&str> = settings
+        .upstreams
+        .iter()
+        .filter_map(|upstream| upstream.leaf_address().ok())
+        .collect();
+
+    let hosts: Map<String, Value> = settings
+        .hosts
+        .iter()
+        .map(|host| {
+            let ips: Vec<&str> = host.ips.iter().map(|ip| ip.trim()).collect();
+            (host.domain.trim().to_string(), json!(ips))
+        })
+        .collect();
+
+    json!({ "servers": servers, "hosts": hosts })
+}
+
+/// Overrides the resolvers of a config's `dns` object when any are set and
+/// adds the static hosts, replacing those the config already has.
+pub fn merge_into(settings: &DnsSettings, dns: &mut Map<String, Value>) {
+    let leaf = to_leaf_dns(settings);
+    if !settings.upstreams.is_empty() {
+        dns.insert("servers".to_string(), leaf["servers"].clone());
+    }
+    if settings.hosts.is_empty() {
+        return;
+    }
+
+    let hosts = dns.entry("hosts").or_insert_with(|| json!({}));
+    if !hosts.is_object() {
+        *hosts = json!({});
+    }
+    if let (Some(hosts), Some(ours)) = (hosts.as_object_mut(), leaf["hosts"].as_object()) {
+        hosts.extend(ours.clone());
+    }
+}
+
+pub fn leaf_dns<R: Runtime>(app: &AppHandle<R>) -> Result<Value> {
+    Ok(to_leaf_dns(&get_settings(app)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream(address: &str) -> DnsUpstream {
+        DnsUpstream {
+            address: address.to_string(),
+        }
+    }
+
+    #[test]
+    fn accepts_plain_resolvers_only() {
+        let settings = DnsSettings {
+            upstreams: vec![
+                upstream("1.1.1.1"),
+                upstream("udp://[2606:4700::1111]:53"),
+                upstream("tls://dns.example:853"),
+                upstream("https://dns.example/dns-query"),
+                upstream("udp://dns.example"),
+            ],
+            ..Default::default()
+        };
+
+        let report = validate_settings(&settings);
+        assert_eq!(report.errors.len(), 3);
+        assert!(report.errors[0].message.contains("tls://dns.example:853"));
+    }
+
+    #[test]
+    fn rejects_bad_hosts() {
+        let settings = DnsSettings {
+            upstreams: vec![],
+            hosts: vec![HostOverride {
+                domain: "nas lan".to_string(),
+                ips: vec!["10.0.0.2".to_string(), "nas".to_string()],
+            }],
+        };
+
+        let report = validate_settings(&settings);
+        let fields: Vec<&str> = report.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["hosts[0]", "hosts[0]"]);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{keyring, persistence, policy, util};
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use notify::{Event as NotifyEvent, EventKind, RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Runtime};
+
+const DROP_FOLDER_FILE: &str = "drop_folder.json";
+const DROP_FOLDER_KEY: &str = "drop_folder";
+
+const IMPORTED_DIR: &str = "imported";
+const FAILED_DIR: &str = "failed";
+
+// The command whose policy entry also covers imports from the folder.
+const IMPORT_COMMAND: &str = "import_offline_subscription";
+const IMPORT_TIMEOUT: Duration = Duration::from_secs(300);
+const SETTLE_INTERVAL: Duration = Duration::from_secs(1);
+const SETTLE_ATTEMPTS: u32 = 30;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DropFolderSettings {
+    pub enabled: bool,
+    pub path: Option<String>,
+}
+
+static WATCHER: Lazy<Mutex<Option<notify::RecommendedWatcher>>> = Lazy::new(|| Mutex::new(None));
+static QUEUED: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+fn is_leafsub(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .map(|extension| extension.eq_ignore_ascii_case("leafsub"))
+            .unwrap_or(false)
+}
+
+pub fn get_settings<R: Runtime>(app: &AppHandle<R>) -> Result<DropFolderSettings> {
+    persistence::load(app, DROP_FOLDER_FILE, DROP_FOLDER_KEY)
+}
+
+pub fn set_settings<R: Runtime>(
+    app: &AppHandle<R>,
+    enabled: bool,
+    path: Option<String>,
+) -> Result<DropFolderSettings> {
+    let path = path
+        .map(|path| path.trim().to_string())
+        .filter(|path| !path.is_empty());
+    if enabled {
+        let Some(path) = path.as_deref() else {
+            return Err(anyhow!("a folder is required to enable auto-import"));
+        };
+        if !Path::new(path).is_dir() {
+            return Err(anyhow!("{} is not a folder", path));
+        }
+    }
+
+    let settings = DropFolderSettings { enabled, path };
+    persistence::save(app, DROP_FOLDER_FILE, DROP_FOLDER_KEY, &settings)?;
+
+    stop();
+    if settings.enabled {
+        start(app.clone())?;
+    }
+    Ok(settings)
+}
+
+// Waits until a file that is still being copied stops growing.
+fn wait_until_settled(path: &Path) -> Result<()> {
+    let mut last_size = None;
+    for _ in 0..SETTLE_ATTEMPTS {
+        let size = fs::metadata(path)?.len();
+        if last_size == Some(size) {
+            return Ok(());
+        }
+        last_size = Some(size);
+        thread::sleep(SETTLE_INTERVAL);
+    }
+    Err(anyhow!("file is still being written"))
+}
+
+// Runs one offline import and waits for its final state. Nobody is there to
+// enter a passphrase, so encrypted files fail and end up in `failed/`.
+fn run_import<R: Runtime>(app: &AppHandle<R>, path: &Path) -> Result<()> {
+    policy::check_command(IMPORT_COMMAND).map_err(|e| anyhow!(e))?;
+    policy::check_import().map_err(|e| anyhow!(e))?;
+
+    wait_until_settled(path)?;
+    let keyring_json = keyring::trusted_keyring_json(app)?;
+
+    util::run_subscription_update(app, IMPORT_TIMEOUT, |callback| {
+        leaf_sdk_desktop::import_offline_subscription(
+            path.to_string_lossy().to_string(),
+            None,
+            keyring_json,
+            move |state| callback(state, None),
+        );
+        Ok(())
+    })
+}
+
+// Moves `path` into `folder/<dir>`, never overwriting an earlier file.
+fn move_into(folder: &Path, dir: &str, path: &Path) -> Result<PathBuf> {
+    let target_dir = folder.join(dir);
+    fs::create_dir_all(&target_dir)?;
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid file name"))?
+        .to_string_lossy()
+        .to_string();
+    let mut target = target_dir.join(&file_name);
+    if target.exists() {
+        target = target_dir.join(format!("{}-{}", util::now(), file_name));
+    }
+
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+fn process<R: Runtime>(app: &AppHandle<R>, folder: &Path, path: &Path) {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    info!("Importing {} from drop folder", name);
+    let (dir, body) = match run_import(app, path) {
+        Ok(()) => (IMPORTED_DIR, format!("Imported subscription from {}", name)),
+        Err(e) => {
+            error!("Drop folder import of {} failed: {}", name, e);
+            (FAILED_DIR, format!("Failed to import {}: {}", name, e))
+        }
+    };
+
+    if let Err(e) = move_into(folder, dir, path) {
+        error!("Failed to move {} to {}/: {}", name, dir, e);
+    }
+    util::notify(app, body);
+}
+
+fn enqueue(sender: &mpsc::Sender<PathBuf>, path: PathBuf) {
+    if is_leafsub(&path) && QUEUED.lock().insert(path.clone()) {
+        let _ = sender.send(path);
+    }
+}
+
+pub fn start<R: Runtime>(app: AppHandle<R>) -> Result<()> {
+    let settings = get_settings(&app)?;
+    let Some(folder) = settings
+        .path
+        .filter(|_| settings.enabled)
+        .map(PathBuf::from)
+    else {
+        return Ok(());
+    };
+
+    let (sender, receiver) = mpsc::channel::<PathBuf>();
+
+    let worker_folder = folder.clone();
+    thread::spawn(move || {
+        for path in receiver {
+            if path.exists() {
+                process(&app, &worker_folder, &path);
+            }
+            QUEUED.lock().remove(&path);
+        }
+    });
+
+    // Files copied while the app was not running.
+    for entry in fs::read_dir(&folder)?.flatten() {
+        enqueue(&sender, entry.path());
+    }
+
+    let watcher_sender = sender.clone();
+    let mut watcher =
+        notify::recommended_watcher(move |res: notify::Result<NotifyEvent>| match res {
+            Ok(event) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        enqueue(&watcher_sender, path);
+                    }
+                }
+            }
+            Err(e) => error!("Drop folder watcher error: {:?}", e),
+        })?;
+    watcher.watch(&folder, RecursiveMode::NonRecursive)?;
+
+    *WATCHER.lock() = Some(watcher);
+    info!("Watching drop folder {}", folder.display());
+    Ok(())
+}
+
+pub fn stop() {
+    // Dropping the watcher also ends the worker once its queue is drained.
+    if WATCHER.lock().take().is_some() {
+        info!("Stopped watching drop folder");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(name: &str) -> PathBuf {
+        let folder =
+            std::env::temp_dir().join(format!("leaf-drop-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn only_picks_up_leafsub_files() {
+        let folder = folder("pick");
+        for name in ["a.leafsub", "b.LEAFSUB", "c.json"] {
+            fs::write(folder.join(name), "").unwrap();
+        }
+        assert!(is_leafsub(&folder.join("a.leafsub")));
+        assert!(is_leafsub(&folder.join("b.LEAFSUB")));
+        assert!(!is_leafsub(&folder.join("c.json")));
+        assert!(!is_leafsub(&folder.join("missing.leafsub")));
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn moves_files_without_overwriting() {
+        let folder = folder("move");
+        for contents in ["first", "second"] {
+            fs::write(folder.join("lab.leafsub"), contents).unwrap();
+            move_into(&folder, IMPORTED_DIR, &folder.join("lab.leafsub")).unwrap();
+        }
+
+        let imported = folder.join(IMPORTED_DIR);
+        assert_eq!(
+            fs::read_to_string(imported.join("lab.leafsub")).unwrap(),
+            "first"
+        );
+        assert_eq!(fs::read_dir(&imported).unwrap().count(), 2);
+        assert!(!folder.join("lab.leafsub").exists());
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use crate::persistence;
+use anyhow::Result;
+use leaf_sdk_desktop::SubscriptionState;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Runtime};
+
+const FETCH_OPTIONS_FILE: &str = "fetch_options.json";
+const FETCH_OPTIONS_KEY: &str = "fetch_options";
+
+/// How one fetch option is chosen.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchSetting {
+    /// Left to the SDK's auto-selection.
+    #[default]
+    Auto,
+    On,
+    Off,
+}
+
+impl FetchSetting {
+    fn forced(self) -> Option<bool> {
+        match self {
+            FetchSetting::Auto => None,
+            FetchSetting::On => Some(true),
+            FetchSetting::Off => Some(false),
+        }
+    }
+}
+
+/// Advanced options for fetching a subscription.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct FetchOptions {
+    pub tls: FetchSetting,
+    pub fragment: FetchSetting,
+}
+
+/// Options a fetch ran with, reported in its final subscription event. `None`
+/// marks an option left to the SDK, which does not report what it chose.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct EffectiveFetchOptions {
+    pub tls: Option<bool>,
+    pub fragment: Option<bool>,
+}
+
+impl FetchOptions {
+    fn effective(self) -> EffectiveFetchOptions {
+        EffectiveFetchOptions {
+            tls: self.tls.forced(),
+            fragment: self.fragment.forced(),
+        }
+    }
+}
+
+pub fn get<R: Runtime>(app: &AppHandle<R>) -> Result<FetchOptions> {
+    persistence::load(app, FETCH_OPTIONS_FILE, FETCH_OPTIONS_KEY)
+}
+
+pub fn set<R: Runtime>(app: &AppHandle<R>, options: FetchOptions) -> Result<()> {
+    persistence::save(app, FETCH_OPTIONS_FILE, FETCH_OPTIONS_KEY, &options)
+}
+
+/// Fetches the subscription of `client_id` with the persisted options and
+/// passes each state on with the options the fetch ran with.
+pub fn update_subscription<R, F>(app: &AppHandle<R>, client_id: String, callback: F) -> Result<()>
+where
+    R: Runtime,
+    F: Fn(SubscriptionState, EffectiveFetchOptions) + Send + Sync + 'static,
+{
+    let options = get(app)?.effective();
+    leaf_sdk_desktop::update_subscription(
+        options.tls,
+        options.fragment,
+        client_id,
+        None,
+        None,
+        move |state| callback(state, options),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_leaves_the_choice_to_the_sdk() {
+        assert_eq!(
+            FetchOptions::default().effective(),
+            EffectiveFetchOptions {
+                tls: None,
+                fragment: None
+            }
+        );
+    }
+
+    #[test]
+    fn forced_options_are_passed_on() {
+        let options = FetchOptions {
+            tls: FetchSetting::On,
+            fragment: FetchSetting::Off,
+        };
+        assert_eq!(
+            options.effective(),
+            EffectiveFetchOptions {
+                tls: Some(true),
+                fragment: Some(false)
+            }
+        );
+    }
+
+    #[test]
+    fn reads_settings_by_name() {
+        let options: FetchOptions =
+            serde_json::from_str(r#"{ "tls": "on", "fragment": "auto" }"#).unwrap();
+        assert_eq!(options.tls, FetchSetting::On);
+        assert!(
+            serde_json::from_str::<FetchOptions>(r#"{ "tls": true, "fragment": null }"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use anyhow::{anyhow, Result};
+use ipnetwork::IpNetwork;
+use maxminddb::geoip2;
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::Ipv6Addr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::SystemTime;
+
+const GEOIP_FILE: &str = "geo.mmdb";
+const GEOSITE_FILE: &str = "site.dat";
+
+// IPv6 databases alias their IPv4 tree at `::/96` under these prefixes too, so
+// walking `::/0` would visit every IPv4 network once per alias.
+const IPV4_ALIASES: [(Ipv6Addr, u8); 3] = [
+    (Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0, 0), 96),
+    (Ipv6Addr::new(0x2001, 0, 0, 0, 0, 0, 0, 0), 32),
+    (Ipv6Addr::new(0x2002, 0, 0, 0, 0, 0, 0, 0), 16),
+];
+
+static ASSET_DIR: OnceCell<PathBuf> = OnceCell::new();
+static CATALOG: Lazy<Mutex<Option<CachedCatalog>>> = Lazy::new(|| Mutex::new(None));
+static WARMING: AtomicBool = AtomicBool::new(false);
+
+// The catalog together with the modification times of the files it was read
+// from, so that updated assets are picked up.
+struct CachedCatalog {
+    modified: Vec<Option<SystemTime>>,
+    catalog: GeoCatalog,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct GeoCategory {
+    pub code: String,
+    pub entries: usize,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct GeoCatalog {
+    pub geoip: Vec<GeoCategory>,
+    pub geosite: Vec<GeoCategory>,
+}
+
+impl GeoCatalog {
+    pub fn has_geoip(&self, code: &str) -> bool {
+        let code = code.to_lowercase();
+        self.geoip.iter().any(|category| category.code == code)
+    }
+
+    pub fn has_geosite(&self, code: &str) -> bool {
+        // `!cn` negates a category and `google@cn` selects the `cn` attribute
+        // of the `google` category.
+        let code = code.strip_prefix('!').unwrap_or(code);
+        let code = code.split('@').next().unwrap_or(code).to_lowercase();
+        self.geosite.iter().any(|category| category.code == code)
+    }
+}
+
+/// Leaf looks for its assets in `ASSET_LOCATION` and falls back to the
+/// directory of its own executable, which is where the sidecar lives.
+pub fn init_asset_dir(sidecar_program: &str) {
+    let dir = match std::env::var_os("ASSET_LOCATION") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(sidecar_program)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+    };
+    let _ = ASSET_DIR.set(dir);
+}
+
+pub fn asset_path(file: &str) -> Result<PathBuf> {
+    let dir = ASSET_DIR
+        .get()
+        .ok_or_else(|| anyhow!("asset directory is not initialized"))?;
+    Ok(dir.join(file))
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf.get(*pos).ok_or_else(|| anyhow!("truncated varint"))?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("varint is too long"))
+}
+
+/// Walks one protobuf message and calls `visit` with the number and payload of
+/// every length-delimited field, skipping all other wire types.
+fn for_each_bytes_field(buf: &[u8], mut visit: impl FnMut(u64, &[u8]) -> Result<()>) -> Result<()> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        let key = read_varint(buf, &mut pos)?;
+        match key & 0x7 {
+            0 => {
+                read_varint(buf, &mut pos)?;
+            }
+            1 => pos += 8,
+            2 => {
+                let len = read_varint(buf, &mut pos)? as usize;
+                let end = pos
+                    .checked_add(len)
+                    .filter(|end| *end <= buf.len())
+                    .ok_or_else(|| anyhow!("truncated field"))?;
+                visit(key >> 3, &buf[pos..end])?;
+                pos = end;
+            }
+            5 => pos += 4,
+            wire_type => return Err(anyhow!("unsupported wire type {}", wire_type)),
+        }
+    }
+    Ok(())
+}
+
+// site.dat is a v2ray `GeoSiteList`: repeated `GeoSite entry = 1`, where each
+// `GeoSite` has `string country_code = 1` and repeated `Domain domain = 2`.
+fn load_geosite(path: &Path) -> Result<Vec<GeoCategory>> {
+    let data = fs::read(path)?;
+    let mut categories = Vec::new();
+
+    for_each_bytes_field(&data, |field, entry| {
+        if field != 1 {
+            return Ok(());
+        }
+
+        let mut code = String::new();
+        let mut entries = 0;
+        for_each_bytes_field(entry, |field, value| {
+            match field {
+                1 => code = String::from_utf8_lossy(value).to_lowercase(),
+                2 => entries += 1,
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        categories.push(GeoCategory { code, entries });
+        Ok(())
+    })?;
+
+    categories.sort_by(|a, b| a.code.cmp(&b.code));
+    Ok(categories)
+}
+
+fn load_geoip(path: &Path) -> Result<Vec<GeoCategory>> {
+    let reader = maxminddb::Reader::open_readfile(path)?;
+    let network: IpNetwork = if reader.metadata.ip_version == 6 {
+        "::/0".parse()?
+    } else {
+        "0.0.0.0/0".parse()?
+    };
+
+    let mut networks = Vec::new();
+    for item in reader.within::<geoip2::Country>(network)? {
+        let item = item?;
+        if let Some(code) = item.info.country.and_then(|country| country.iso_code) {
+            networks.push((item.ip_net, code.to_string()));
+        }
+    }
+
+    Ok(count_networks(networks))
+}
+
+fn is_ipv4_alias(network: &IpNetwork) -> bool {
+    let IpNetwork::V6(network) = network else {
+        return false;
+    };
+    IPV4_ALIASES.iter().any(|(prefix, len)| {
+        network.prefix() >= *len
+            && u128::from(network.network()) >> (128 - len) == u128::from(*prefix) >> (128 - len)
+    })
+}
+
+/// Counts the distinct networks of every country, skipping the IPv4 aliases.
+fn count_networks(networks: impl IntoIterator<Item = (IpNetwork, String)>) -> Vec<GeoCategory> {
+    let mut seen = HashSet::new();
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for (network, code) in networks {
+        let code = code.to_lowercase();
+        if !is_ipv4_alias(&network) && seen.insert((network, code.clone())) {
+            *counts.entry(code).or_default() += 1;
+        }
+    }
+
+    counts
+        .into_iter()
+        .map(|(code, entries)| GeoCategory { code, entries })
+        .collect()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+pub fn load_catalog() -> Result<GeoCatalog> {
+    let paths = [asset_path(GEOIP_FILE)?, asset_path(GEOSITE_FILE)?];
+    let modified: Vec<_> = paths.iter().map(|path| modified(path)).collect();
+
+    if let Some(cached) = CATALOG.lock().as_ref() {
+        if cached.modified == modified {
+            return Ok(cached.catalog.clone());
+        }
+    }
+
+    let catalog = GeoCatalog {
+        geoip: load_geoip(&paths[0])?,
+        geosite: load_geosite(&paths[1])?,
+    };
+    *CATALOG.lock() = Some(CachedCatalog {
+        modified,
+        catalog: catalog.clone(),
+    });
+
+    Ok(catalog)
+}
+
+/// Drops the cached catalog so the next lookup reads the assets again.
+pub fn invalidate_catalog() {
+    CATALOG.lock().take();
+}
+
+/// Reads the catalog on a background thread, so that later lookups find it
+/// cached. Does nothing while another thread is already reading it.
+pub fn warm_catalog() {
+    if WARMING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    thread::spawn(|| {
+        if let Err(e) = load_catalog() {
+            log::warn!("Geo catalog is unavailable: {}", e);
+        }
+        WARMING.store(false, Ordering::SeqCst);
+    });
+}
+
+/// Returns the cached catalog without reading the assets, since callers that
+/// only use it to tighten validation run on the main thread. A cold cache is
+/// filled in the background and `None` is returned until then.
+pub fn try_catalog() -> Option<GeoCatalog> {
+    let cached = CATALOG.lock().as_ref().map(|cached| cached.catalog.clone());
+    if cached.is_none() {
+        warm_catalog();
+    }
+    cached
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(value: &str) -> IpNetwork {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn counts_ipv4_networks_once() {
+        let networks = [
+            (network("::1.0.0.0/120"), "AU".to_string()),
+            (network("::ffff:1.0.0.0/120"), "AU".to_string()),
+            (network("2002:100::/24"), "AU".to_string()),
+            (network("2001:0:100::/40"), "AU".to_string()),
+            (network("2400:cb00::/32"), "AU".to_string()),
+            (network("2400:cb00::/32"), "au".to_string()),
+            (network("2a00::/12"), "DE".to_string()),
+        ];
+
+        let counts: Vec<(String, usize)> = count_networks(networks)
+            .into_iter()
+            .map(|category| (category.code, category.entries))
+            .collect();
+        assert_eq!(counts, [("au".to_string(), 2), ("de".to_string(), 1)]);
+    }
+
+    #[test]
+    fn matches_negated_and_attributed_geosite_codes() {
+        let catalog = GeoCatalog {
+            geoip: vec![],
+            geosite: vec![GeoCategory {
+                code: "google".to_string(),
+                entries: 1,
+            }],
+        };
+        assert!(catalog.has_geosite("Google"));
+        assert!(catalog.has_geosite("!google"));
+        assert!(catalog.has_geosite("google@cn"));
+        assert!(!catalog.has_geosite("!cn"));
+    }
+}
@@ -1,30 +1,670 @@
+use crate::{geodata, policy};
 use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fmt;
 use sysinfo::System;
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct UpdateLeafPreferences {
-    enable_ipv6: bool,
-    prefer_ipv6: bool,
-    memory_logger: bool,
-    log_level: i32,
-    api_port: u16,
-    auto_reload: bool,
-    user_agent: String,
-    bypass_lan: bool,
-    bypass_lan_in_core: bool,
-    fake_ip: bool,
-    force_resolve_domain: bool,
-    internal_dns_server: bool,
-    bypass_geoip_list: Option<Vec<String>>,
-    bypass_geosite_list: Option<Vec<String>>,
-    reject_geoip_list: Option<Vec<String>>,
-    reject_geosite_list: Option<Vec<String>>,
-}
-
-pub fn set_preferences(preferences: String) -> Result<()> {
-    let update_preferences: UpdateLeafPreferences = serde_json::from_str(&preferences)?;
+    pub enable_ipv6: bool,
+    pub prefer_ipv6: bool,
+    pub memory_logger: bool,
+    pub log_level: i32,
+    pub api_port: u16,
+    pub auto_reload: bool,
+    pub user_agent: String,
+    pub bypass_lan: bool,
+    pub bypass_lan_in_core: bool,
+    pub fake_ip: bool,
+    pub force_resolve_domain: bool,
+    pub internal_dns_server: bool,
+    pub bypass_geoip_list: Option<Vec<String>>,
+    pub bypass_geosite_list: Option<Vec<String>>,
+    pub reject_geoip_list: Option<Vec<String>>,
+    pub reject_geosite_list: Option<Vec<String>>,
+}
+
+impl UpdateLeafPreferences {
+    /// Trims the user agent and every geo list entry, which is how they are
+    /// validated and saved.
+    pub fn normalize(&mut self) {
+        self.user_agent = self.user_agent.trim().to_string();
+        for list in [
+            &mut self.bypass_geoip_list,
+            &mut self.bypass_geosite_list,
+            &mut self.reject_geoip_list,
+            &mut self.reject_geosite_list,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            for code in list.iter_mut() {
+                *code = code.trim().to_string();
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FieldKind {
+    Bool,
+    Integer,
+    Port,
+    String,
+    StringList,
+}
+
+const PREFERENCE_FIELDS: &[(&str, FieldKind)] = &[
+    ("enable_ipv6", FieldKind::Bool),
+    ("prefer_ipv6", FieldKind::Bool),
+    ("memory_logger", FieldKind::Bool),
+    ("log_level", FieldKind::Integer),
+    ("api_port", FieldKind::Port),
+    ("auto_reload", FieldKind::Bool),
+    ("user_agent", FieldKind::String),
+    ("bypass_lan", FieldKind::Bool),
+    ("bypass_lan_in_core", FieldKind::Bool),
+    ("fake_ip", FieldKind::Bool),
+    ("force_resolve_domain", FieldKind::Bool),
+    ("internal_dns_server", FieldKind::Bool),
+    ("bypass_geoip_list", FieldKind::StringList),
+    ("bypass_geosite_list", FieldKind::StringList),
+    ("reject_geoip_list", FieldKind::StringList),
+    ("reject_geosite_list", FieldKind::StringList),
+];
+
+// Read-only fields of `LeafPreferences` that the frontend sends back along with the
+// editable ones. They are dropped silently instead of being reported as unknown.
+const READ_ONLY_FIELDS: &[&str] = &[
+    "client_id",
+    "last_update_time",
+    "traffic",
+    "used_traffic",
+    "expire_time",
+    "locked_fields",
+];
+
+const MIN_API_PORT: u16 = 1024;
+const DEFAULT_API_PORT: u16 = 10001;
+const DEFAULT_LOG_LEVEL: i32 = 2;
+const MAX_LOG_LEVEL: i32 = 4;
+
+// Serializes every read-merge-write of the preferences so concurrent writers
+// cannot overwrite each other's changes.
+static PREFERENCES_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FieldIssue {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub errors: Vec<FieldIssue>,
+    pub warnings: Vec<FieldIssue>,
+}
+
+impl ValidationReport {
+    pub fn error(&mut self, field: &str, message: impl Into<String>) {
+        self.errors.push(FieldIssue {
+            field: field.to_string(),
+            message: message.into(),
+        });
+    }
+
+    pub fn warning(&mut self, field: &str, message: impl Into<String>) {
+        self.warnings.push(FieldIssue {
+            field: field.to_string(),
+            message: message.into(),
+        });
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Why preferences were not saved. Invalid input carries the full report so
+/// the frontend can point at the offending fields.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", content = "data", rename_all = "lowercase")]
+pub enum PreferencesError {
+    Invalid(ValidationReport),
+    Failed(String),
+}
+
+impl From<anyhow::Error> for PreferencesError {
+    fn from(error: anyhow::Error) -> Self {
+        PreferencesError::Failed(error.to_string())
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let issues: Vec<String> = self
+            .errors
+            .iter()
+            .map(|issue| format!("{}: {}", issue.field, issue.message))
+            .collect();
+        write!(f, "{}", issues.join("; "))
+    }
+}
+
+fn check_field_type(report: &mut ValidationReport, field: &str, kind: FieldKind, value: &Value) {
+    let valid = match kind {
+        FieldKind::Bool => value.is_boolean(),
+        FieldKind::Integer => value.as_i64().is_some_and(|v| i32::try_from(v).is_ok()),
+        FieldKind::Port => {
+            if let Some(port) = value.as_i64() {
+                if !(1..=u16::MAX as i64).contains(&port) {
+                    report.error(field, format!("port {} is out of range 1-65535", port));
+                }
+                true
+            } else {
+                false
+            }
+        }
+        FieldKind::String => value.is_string(),
+        FieldKind::StringList => match value {
+            Value::Null => true,
+            Value::Array(items) => items.iter().all(Value::is_string),
+            _ => false,
+        },
+    };
+
+    if !valid {
+        let expected = match kind {
+            FieldKind::Bool => "a boolean",
+            FieldKind::Integer => "an integer",
+            FieldKind::Port => "a port number",
+            FieldKind::String => "a string",
+            FieldKind::StringList => "a list of strings",
+        };
+        report.error(field, format!("expected {}", expected));
+    }
+}
+
+fn check_shape(report: &mut ValidationReport, object: &Map<String, Value>) {
+    for (field, kind) in PREFERENCE_FIELDS {
+        match object.get(*field) {
+            Some(value) => check_field_type(report, field, *kind, value),
+            None if matches!(kind, FieldKind::StringList) => {}
+            None => report.error(field, "missing field"),
+        }
+    }
+
+    for key in object.keys() {
+        let known = PREFERENCE_FIELDS.iter().any(|(field, _)| field == key)
+            || READ_ONLY_FIELDS.contains(&key.as_str());
+        if !known {
+            report.warning(key, "unknown field is ignored");
+        }
+    }
+}
+
+fn is_geoip_code(code: &str) -> bool {
+    code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+fn is_geosite_code(code: &str) -> bool {
+    code.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '!' | '@'))
+}
+
+fn check_geo_list(
+    report: &mut ValidationReport,
+    field: &str,
+    list: &Option<Vec<String>>,
+    is_valid_code: fn(&str) -> bool,
+    hint: &str,
+    is_known: &dyn Fn(&str) -> bool,
+) {
+    let Some(list) = list else {
+        return;
+    };
+
+    let mut seen = HashSet::new();
+    for code in list {
+        let trimmed = code.trim();
+        if trimmed.is_empty() {
+            report.error(field, "entries must not be empty");
+        } else if !is_valid_code(trimmed) {
+            report.error(field, format!("'{}' is not {}", trimmed, hint));
+        } else if !is_known(trimmed) {
+            // The database may just be older than the code, so leave it to leaf.
+            report.warning(
+                field,
+                format!("'{}' is not in the installed geo database", trimmed),
+            );
+        } else if !seen.insert(trimmed.to_lowercase()) {
+            report.warning(field, format!("'{}' is listed more than once", trimmed));
+        }
+    }
+}
+
+pub fn validate_update_preferences(preferences: &UpdateLeafPreferences) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    if preferences.prefer_ipv6 && !preferences.enable_ipv6 {
+        report.error("prefer_ipv6", "requires enable_ipv6 to be turned on");
+    }
+
+    if !(0..=MAX_LOG_LEVEL).contains(&preferences.log_level) {
+        report.error(
+            "log_level",
+            format!("must be between 0 and {}", MAX_LOG_LEVEL),
+        );
+    }
+
+    if preferences.api_port == 0 {
+        report.error("api_port", "port 0 is not allowed");
+    } else if preferences.api_port < MIN_API_PORT {
+        report.warning(
+            "api_port",
+            format!(
+                "ports below {} may require elevated privileges",
+                MIN_API_PORT
+            ),
+        );
+    }
+
+    if preferences.user_agent.trim().is_empty() {
+        report.warning(
+            "user_agent",
+            "an empty user agent may be rejected by the server",
+        );
+    }
+
+    if preferences.bypass_lan_in_core && !preferences.bypass_lan {
+        report.warning(
+            "bypass_lan_in_core",
+            "has no effect while bypass_lan is off",
+        );
+    }
+
+    let catalog = geodata::try_catalog();
+    let geoip_known = |code: &str| catalog.as_ref().is_none_or(|c| c.has_geoip(code));
+    let geosite_known = |code: &str| catalog.as_ref().is_none_or(|c| c.has_geosite(code));
+
+    check_geo_list(
+        &mut report,
+        "bypass_geoip_list",
+        &preferences.bypass_geoip_list,
+        is_geoip_code,
+        "a 2-letter country code",
+        &geoip_known,
+    );
+    check_geo_list(
+        &mut report,
+        "reject_geoip_list",
+        &preferences.reject_geoip_list,
+        is_geoip_code,
+        "a 2-letter country code",
+        &geoip_known,
+    );
+    check_geo_list(
+        &mut report,
+        "bypass_geosite_list",
+        &preferences.bypass_geosite_list,
+        is_geosite_code,
+        "a geosite category",
+        &geosite_known,
+    );
+    check_geo_list(
+        &mut report,
+        "reject_geosite_list",
+        &preferences.reject_geosite_list,
+        is_geosite_code,
+        "a geosite category",
+        &geosite_known,
+    );
+
+    report
+}
+
+pub fn parse_preferences(preferences: &str) -> (Option<UpdateLeafPreferences>, ValidationReport) {
+    let mut report = ValidationReport::default();
+
+    let value: Value = match serde_json::from_str(preferences) {
+        Ok(value) => value,
+        Err(e) => {
+            report.error("", format!("invalid JSON: {}", e));
+            return (None, report);
+        }
+    };
+
+    let Some(object) = value.as_object() else {
+        report.error("", "expected a JSON object");
+        return (None, report);
+    };
+
+    check_shape(&mut report, object);
+    if !report.is_valid() {
+        return (None, report);
+    }
+
+    let mut update_preferences: UpdateLeafPreferences = match serde_json::from_value(value) {
+        Ok(update_preferences) => update_preferences,
+        Err(e) => {
+            report.error("", e.to_string());
+            return (None, report);
+        }
+    };
+    update_preferences.normalize();
+
+    let semantic = validate_update_preferences(&update_preferences);
+    report.errors.extend(semantic.errors);
+    report.warnings.extend(semantic.warnings);
+
+    (Some(update_preferences), report)
+}
+
+pub fn validate_preferences(preferences: String) -> ValidationReport {
+    parse_preferences(&preferences).1
+}
+
+pub fn set_preferences(preferences: String) -> Result<PreferencesUpdate, PreferencesError> {
+    let (update_preferences, report) = parse_preferences(&preferences);
+
+    let update_preferences = match update_preferences {
+        Some(update_preferences) if report.is_valid() => update_preferences,
+        _ => return Err(PreferencesError::Invalid(report)),
+    };
+
+    let _guard = PREFERENCES_LOCK.lock();
+    let applied = apply_preferences(update_preferences)?;
+
+    Ok(PreferencesUpdate {
+        report,
+        ..PreferencesUpdate::from_applied(applied)
+    })
+}
+
+pub type PreferencesGuard = MutexGuard<'static, ()>;
+
+/// Takes the preferences lock for callers that change more than the
+/// preferences in one step, see [`apply_locked`].
+pub fn lock_preferences() -> PreferencesGuard {
+    PREFERENCES_LOCK.lock()
+}
+
+/// Validates and applies `update_preferences` while the caller holds the lock.
+pub fn apply_locked(
+    _guard: &PreferencesGuard,
+    update_preferences: UpdateLeafPreferences,
+) -> Result<AppliedPreferences> {
+    let report = validate_update_preferences(&update_preferences);
+    if !report.is_valid() {
+        return Err(anyhow!("invalid preferences: {}", report));
+    }
+
+    apply_preferences(update_preferences)
+}
+
+pub fn apply_update_preferences(
+    update_preferences: UpdateLeafPreferences,
+) -> Result<AppliedPreferences> {
+    apply_locked(&lock_preferences(), update_preferences)
+}
+
+// The defaults of `src/utils/defaultPreferences.ts`, used for fields the SDK
+// has not stored yet. The user agent is left empty as only the frontend knows it.
+fn default_value(field: &str) -> Value {
+    match field {
+        "enable_ipv6" | "memory_logger" | "bypass_lan" | "bypass_lan_in_core" => Value::Bool(true),
+        "log_level" => Value::from(DEFAULT_LOG_LEVEL),
+        "api_port" => Value::from(DEFAULT_API_PORT),
+        "user_agent" => Value::String(String::new()),
+        field if field.ends_with("_list") => Value::Null,
+        _ => Value::Bool(false),
+    }
+}
+
+// Keeps only the editable fields and fills the missing or null ones.
+fn fill_defaults(object: &mut Map<String, Value>) {
+    object.retain(|key, _| PREFERENCE_FIELDS.iter().any(|(field, _)| field == key));
+    for (field, _) in PREFERENCE_FIELDS {
+        let entry = object.entry(field.to_string()).or_insert(Value::Null);
+        if entry.is_null() {
+            *entry = default_value(field);
+        }
+    }
+}
+
+fn preferences_to_value(preferences: &leaf_sdk_desktop::LeafPreferences) -> Result<Value> {
+    let mut value = serde_json::to_value(preferences)?;
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("stored preferences are not an object"))?;
+
+    fill_defaults(object);
+    Ok(value)
+}
+
+/// When a changed preference takes effect, ordered from least to most disruptive.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeEffect {
+    Immediate,
+    Reload,
+    Restart,
+}
+
+impl ChangeEffect {
+    pub fn of_field(field: &str) -> Self {
+        match field {
+            // Only read when a subscription is fetched or by the frontend.
+            "user_agent" | "auto_reload" => ChangeEffect::Immediate,
+            // Baked into the TUN device set up by the core.
+            "enable_ipv6" => ChangeEffect::Restart,
+            _ => ChangeEffect::Reload,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PreferenceChange {
+    pub field: String,
+    pub old: Value,
+    pub new: Value,
+    pub effect: ChangeEffect,
+}
+
+/// The preferences an apply wrote, together with how they differ from the
+/// ones stored before.
+#[derive(Clone, Debug)]
+pub struct AppliedPreferences {
+    pub preferences: UpdateLeafPreferences,
+    pub changes: Vec<PreferenceChange>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct PreferencesUpdate {
+    pub report: ValidationReport,
+    pub changes: Vec<PreferenceChange>,
+    pub reloaded: bool,
+    pub restart_required: bool,
+    #[serde(skip)]
+    pub applied: Option<UpdateLeafPreferences>,
+}
+
+impl PreferencesUpdate {
+    pub fn from_applied(applied: AppliedPreferences) -> Self {
+        PreferencesUpdate {
+            changes: applied.changes,
+            applied: Some(applied.preferences),
+            ..Default::default()
+        }
+    }
+
+    pub fn effect(&self) -> Option<ChangeEffect> {
+        self.changes.iter().map(|change| change.effect).max()
+    }
+}
+
+/// What `patch_preferences` returns: the preferences after the patch, and
+/// what applying it did.
+#[derive(Serialize)]
+pub struct PatchedPreferences {
+    pub preferences: leaf_sdk_desktop::LeafPreferences,
+    pub update: PreferencesUpdate,
+}
+
+pub fn diff_preferences(
+    old: &UpdateLeafPreferences,
+    new: &UpdateLeafPreferences,
+) -> Vec<PreferenceChange> {
+    let old = serde_json::to_value(old).unwrap_or_default();
+    let new = serde_json::to_value(new).unwrap_or_default();
+
+    // An unset list and an empty list mean the same thing to leaf.
+    let normalized = |value: &Value, field: &str, kind: FieldKind| match value.get(field) {
+        Some(Value::Null) | None if matches!(kind, FieldKind::StringList) => {
+            Value::Array(Vec::new())
+        }
+        Some(value) => value.clone(),
+        None => Value::Null,
+    };
+
+    PREFERENCE_FIELDS
+        .iter()
+        .filter_map(|(field, kind)| {
+            let old = normalized(&old, field, *kind);
+            let new = normalized(&new, field, *kind);
+            (old != new).then(|| PreferenceChange {
+                field: field.to_string(),
+                old,
+                new,
+                effect: ChangeEffect::of_field(field),
+            })
+        })
+        .collect()
+}
+
+pub fn current_preferences() -> Result<UpdateLeafPreferences> {
+    let preferences = leaf_sdk_desktop::get_preferences()?;
+    Ok(serde_json::from_value(preferences_to_value(&preferences)?)?)
+}
+
+pub fn patch_preferences(
+    patch: String,
+) -> Result<(leaf_sdk_desktop::LeafPreferences, AppliedPreferences)> {
+    let patch: Value = serde_json::from_str(&patch)?;
+    let patch = patch
+        .as_object()
+        .ok_or_else(|| anyhow!("expected a JSON object"))?;
+
+    let report = check_patch(patch);
+    if !report.is_valid() {
+        return Err(anyhow!("invalid preferences: {}", report));
+    }
+
+    let applied = merge_preferences(patch)?;
+
+    Ok((leaf_sdk_desktop::get_preferences()?, applied))
+}
+
+/// Checks that every key of a partial update is an editable preference of the
+/// right type.
+pub fn check_patch(patch: &Map<String, Value>) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    for (key, value) in patch {
+        match PREFERENCE_FIELDS.iter().find(|(field, _)| field == key) {
+            Some((field, kind)) => check_field_type(&mut report, field, *kind, value),
+            None => report.error(key, "unknown or read-only field"),
+        }
+    }
+    report
+}
+
+fn merge_preferences(patch: &Map<String, Value>) -> Result<AppliedPreferences> {
+    let _guard = PREFERENCES_LOCK.lock();
+
+    let mut merged = preferences_to_value(&leaf_sdk_desktop::get_preferences()?)?;
+    if let Some(object) = merged.as_object_mut() {
+        for (key, value) in patch {
+            object.insert(key.clone(), value.clone());
+        }
+    }
+
+    let update_preferences: UpdateLeafPreferences = serde_json::from_value(merged)?;
+    let report = validate_update_preferences(&update_preferences);
+    if !report.is_valid() {
+        return Err(anyhow!("invalid preferences: {}", report));
+    }
 
+    apply_preferences(update_preferences)
+}
+
+/// Forces the preferences pinned by the managed policy, if any.
+pub fn enforce_policy() -> Result<Vec<PreferenceChange>> {
+    let forced = policy::forced_preferences();
+    if forced.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let report = check_patch(forced);
+    if !report.is_valid() {
+        return Err(anyhow!("invalid policy preferences: {}", report));
+    }
+
+    Ok(merge_preferences(forced)?.changes)
+}
+
+fn check_locked(changes: &[PreferenceChange]) -> Result<()> {
+    if !changes.is_empty() {
+        policy::check_loaded().map_err(|e| anyhow!(e))?;
+    }
+    locked_changes(policy::forced_preferences(), changes)
+}
+
+fn locked_changes(forced: &Map<String, Value>, changes: &[PreferenceChange]) -> Result<()> {
+    // Changes carry unset lists as `[]`, which is what a forced `null` means.
+    let empty_if_null = |value: &Value| match value {
+        Value::Null => Value::Array(Vec::new()),
+        value => value.clone(),
+    };
+    let locked: Vec<&str> = changes
+        .iter()
+        .filter(|change| {
+            forced
+                .get(&change.field)
+                .is_some_and(|value| empty_if_null(value) != empty_if_null(&change.new))
+        })
+        .map(|change| change.field.as_str())
+        .collect();
+
+    if locked.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("locked by policy: {}", locked.join(", ")))
+    }
+}
+
+/// Returns the stored preferences together with the fields locked by policy.
+pub fn get_preferences() -> Result<Value> {
+    let mut preferences = serde_json::to_value(leaf_sdk_desktop::get_preferences()?)?;
+    if let Some(object) = preferences.as_object_mut() {
+        object.insert(
+            "locked_fields".to_string(),
+            serde_json::to_value(policy::locked_fields())?,
+        );
+    }
+    Ok(preferences)
+}
+
+// Callers must hold `PREFERENCES_LOCK`.
+fn apply_preferences(mut update_preferences: UpdateLeafPreferences) -> Result<AppliedPreferences> {
+    update_preferences.normalize();
+    let changes = diff_preferences(&current_preferences()?, &update_preferences);
+    check_locked(&changes)?;
+
+    let applied = update_preferences.clone();
     leaf_sdk_desktop::set_preferences(
         update_preferences.enable_ipv6,
         update_preferences.prefer_ipv6,
@@ -42,7 +682,12 @@ pub fn set_preferences(preferences: String) -> Result<()> {
         update_preferences.reject_geoip_list,
         update_preferences.reject_geosite_list,
         update_preferences.internal_dns_server,
-    )
+    )?;
+
+    Ok(AppliedPreferences {
+        preferences: applied,
+        changes,
+    })
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -78,3 +723,218 @@ pub fn detect_linux_system_info() -> Result<LinuxSystemInfo> {
         arch,
     })
 }
+
+/// Valid preferences for the tests of every module that handles them.
+#[cfg(test)]
+pub fn test_preferences() -> UpdateLeafPreferences {
+    UpdateLeafPreferences {
+        enable_ipv6: true,
+        prefer_ipv6: false,
+        memory_logger: true,
+        log_level: 2,
+        api_port: 10001,
+        auto_reload: false,
+        user_agent: "leaf".to_string(),
+        bypass_lan: true,
+        bypass_lan_in_core: true,
+        fake_ip: false,
+        force_resolve_domain: false,
+        internal_dns_server: false,
+        bypass_geoip_list: None,
+        bypass_geosite_list: None,
+        reject_geoip_list: None,
+        reject_geosite_list: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields(issues: &[FieldIssue]) -> Vec<&str> {
+        issues.iter().map(|issue| issue.field.as_str()).collect()
+    }
+
+    #[test]
+    fn accepts_defaults() {
+        let report = validate_update_preferences(&test_preferences());
+        assert!(report.is_valid());
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn prefer_ipv6_requires_ipv6() {
+        let mut preferences = test_preferences();
+        preferences.enable_ipv6 = false;
+        preferences.prefer_ipv6 = true;
+        assert_eq!(
+            fields(&validate_update_preferences(&preferences).errors),
+            ["prefer_ipv6"]
+        );
+    }
+
+    #[test]
+    fn privileged_api_port_is_a_warning() {
+        let mut preferences = test_preferences();
+        preferences.api_port = 80;
+        let report = validate_update_preferences(&preferences);
+        assert!(report.is_valid());
+        assert_eq!(fields(&report.warnings), ["api_port"]);
+
+        preferences.api_port = 0;
+        assert_eq!(
+            fields(&validate_update_preferences(&preferences).errors),
+            ["api_port"]
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_log_level() {
+        let mut preferences = test_preferences();
+        preferences.log_level = MAX_LOG_LEVEL + 1;
+        assert_eq!(
+            fields(&validate_update_preferences(&preferences).errors),
+            ["log_level"]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_geo_codes() {
+        let mut preferences = test_preferences();
+        preferences.bypass_geoip_list = Some(vec!["cn".to_string(), "china".to_string()]);
+        preferences.reject_geosite_list = Some(vec![" ".to_string()]);
+        assert_eq!(
+            fields(&validate_update_preferences(&preferences).errors),
+            ["bypass_geoip_list", "reject_geosite_list"]
+        );
+    }
+
+    #[test]
+    fn warns_about_codes_missing_from_the_database() {
+        let mut report = ValidationReport::default();
+        let list = Some(vec!["cn".to_string(), "xx".to_string()]);
+        check_geo_list(
+            &mut report,
+            "bypass_geoip_list",
+            &list,
+            is_geoip_code,
+            "a 2-letter country code",
+            &|code| code == "cn",
+        );
+        assert!(report.is_valid());
+        assert_eq!(fields(&report.warnings), ["bypass_geoip_list"]);
+    }
+
+    #[test]
+    fn locks_only_fields_the_policy_forces_differently() {
+        let forced = json!({ "bypass_lan": true, "reject_geosite_list": null });
+        let forced = forced.as_object().unwrap();
+        let change = |field: &str, new: Value| PreferenceChange {
+            field: field.to_string(),
+            old: Value::Null,
+            new,
+            effect: ChangeEffect::of_field(field),
+        };
+
+        assert!(locked_changes(forced, &[change("reject_geosite_list", json!([]))]).is_ok());
+        assert!(locked_changes(forced, &[change("bypass_lan", json!(true))]).is_ok());
+        assert!(locked_changes(forced, &[change("fake_ip", json!(true))]).is_ok());
+        assert!(locked_changes(forced, &[change("bypass_lan", json!(false))]).is_err());
+    }
+
+    #[test]
+    fn parse_reports_shape_errors_and_unknown_fields() {
+        let mut value = serde_json::to_value(test_preferences()).unwrap();
+        value["log_level"] = json!("debug");
+        value["theme"] = json!("dark");
+        value["client_id"] = json!("ignored");
+
+        let (parsed, report) = parse_preferences(&value.to_string());
+        assert!(parsed.is_none());
+        assert_eq!(fields(&report.errors), ["log_level"]);
+        assert_eq!(fields(&report.warnings), ["theme"]);
+    }
+
+    #[test]
+    fn parse_rejects_non_objects() {
+        let (parsed, report) = parse_preferences("[]");
+        assert!(parsed.is_none());
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn parse_normalizes_values() {
+        let mut value = serde_json::to_value(test_preferences()).unwrap();
+        value["user_agent"] = json!("  leaf  ");
+        value["bypass_geoip_list"] = json!([" cn "]);
+
+        let (parsed, report) = parse_preferences(&value.to_string());
+        assert!(report.is_valid());
+        let parsed = parsed.unwrap();
+        assert_eq!(parsed.user_agent, "leaf");
+        assert_eq!(parsed.bypass_geoip_list, Some(vec!["cn".to_string()]));
+    }
+
+    #[test]
+    fn diff_treats_null_and_empty_lists_alike() {
+        let old = test_preferences();
+        let mut new = test_preferences();
+        new.bypass_geoip_list = Some(Vec::new());
+        assert!(diff_preferences(&old, &new).is_empty());
+
+        new.log_level = 4;
+        new.enable_ipv6 = false;
+        let changes = diff_preferences(&old, &new);
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| (change.field.as_str(), change.effect))
+                .collect::<Vec<_>>(),
+            [
+                ("enable_ipv6", ChangeEffect::Restart),
+                ("log_level", ChangeEffect::Reload),
+            ]
+        );
+    }
+
+    #[test]
+    fn null_fields_fall_back_to_defaults() {
+        let mut object = json!({ "api_port": null, "fake_ip": true, "traffic": 5 })
+            .as_object()
+            .unwrap()
+            .clone();
+        fill_defaults(&mut object);
+
+        assert_eq!(object["api_port"], json!(DEFAULT_API_PORT));
+        assert_eq!(object["log_level"], json!(DEFAULT_LOG_LEVEL));
+        assert_eq!(object["bypass_lan"], json!(true));
+        assert_eq!(object["fake_ip"], json!(true));
+        assert_eq!(object["reject_geoip_list"], Value::Null);
+        assert!(!object.contains_key("traffic"));
+        assert!(serde_json::from_value::<UpdateLeafPreferences>(Value::Object(object)).is_ok());
+    }
+
+    #[test]
+    fn classifies_change_effects() {
+        assert_eq!(
+            ChangeEffect::of_field("user_agent"),
+            ChangeEffect::Immediate
+        );
+        assert_eq!(ChangeEffect::of_field("bypass_lan"), ChangeEffect::Reload);
+        assert_eq!(
+            ChangeEffect::of_field("reject_geoip_list"),
+            ChangeEffect::Reload
+        );
+        assert_eq!(ChangeEffect::of_field("enable_ipv6"), ChangeEffect::Restart);
+        assert!(Some(ChangeEffect::Restart) >= Some(ChangeEffect::Reload));
+        assert!(None < Some(ChangeEffect::Immediate));
+    }
+
+    #[test]
+    fn patch_only_accepts_editable_fields() {
+        let patch = json!({ "fake_ip": true, "traffic": 1, "api_port": "x" });
+        let report = check_patch(patch.as_object().unwrap());
+        assert_eq!(fields(&report.errors), ["api_port", "traffic"]);
+    }
+}
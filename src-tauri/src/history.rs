@@ -0,0 +1,173 @@
+use crate::helper::{self, AppliedPreferences, PreferenceChange, UpdateLeafPreferences};
+use crate::{persistence, util};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Runtime};
+
+const HISTORY_FILE: &str = "history.json";
+const HISTORY_KEY: &str = "snapshots";
+const MAX_SNAPSHOTS: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PreferenceSnapshot {
+    pub id: u64,
+    pub timestamp: u64,
+    pub changed_fields: Vec<String>,
+    pub preferences: UpdateLeafPreferences,
+}
+
+fn load<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<PreferenceSnapshot>> {
+    persistence::load(app, HISTORY_FILE, HISTORY_KEY)
+}
+
+// Rebuilds the preferences as they were before `changes` were applied.
+fn previous_preferences(
+    current: &UpdateLeafPreferences,
+    changes: &[PreferenceChange],
+) -> Result<UpdateLeafPreferences> {
+    let mut value = serde_json::to_value(current)?;
+    if let Some(object) = value.as_object_mut() {
+        for change in changes {
+            object.insert(change.field.clone(), change.old.clone());
+        }
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+// Appends the snapshot of `applied`. The first one is preceded by the
+// baseline from before the change, which is kept when old snapshots are
+// dropped so that the original preferences can always be restored.
+fn push_snapshot(
+    snapshots: &mut Vec<PreferenceSnapshot>,
+    applied: &UpdateLeafPreferences,
+    changes: &[PreferenceChange],
+    timestamp: u64,
+) -> Result<()> {
+    if snapshots.is_empty() {
+        snapshots.push(PreferenceSnapshot {
+            id: 1,
+            timestamp,
+            changed_fields: Vec::new(),
+            preferences: previous_preferences(applied, changes)?,
+        });
+    }
+
+    let id = snapshots.last().map_or(1, |snapshot| snapshot.id + 1);
+    snapshots.push(PreferenceSnapshot {
+        id,
+        timestamp,
+        changed_fields: changes.iter().map(|change| change.field.clone()).collect(),
+        preferences: applied.clone(),
+    });
+
+    if snapshots.len() > MAX_SNAPSHOTS {
+        let excess = snapshots.len() - MAX_SNAPSHOTS;
+        snapshots.drain(1..=excess);
+    }
+    Ok(())
+}
+
+/// Stores a snapshot of `applied`, the preferences written along with
+/// `changes`.
+pub fn record<R: Runtime>(
+    app: &AppHandle<R>,
+    applied: &UpdateLeafPreferences,
+    changes: &[PreferenceChange],
+) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let mut snapshots = load(app)?;
+    push_snapshot(&mut snapshots, applied, changes, util::now())?;
+    persistence::save(app, HISTORY_FILE, HISTORY_KEY, &snapshots)
+}
+
+pub fn list<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<PreferenceSnapshot>> {
+    let mut snapshots = load(app)?;
+    snapshots.reverse();
+    Ok(snapshots)
+}
+
+fn find(snapshots: &[PreferenceSnapshot], id: u64) -> Result<&PreferenceSnapshot> {
+    snapshots
+        .iter()
+        .find(|snapshot| snapshot.id == id)
+        .ok_or_else(|| anyhow!("snapshot {} does not exist", id))
+}
+
+pub fn diff<R: Runtime>(app: &AppHandle<R>, from: u64, to: u64) -> Result<Vec<PreferenceChange>> {
+    let snapshots = load(app)?;
+    Ok(helper::diff_preferences(
+        &find(&snapshots, from)?.preferences,
+        &find(&snapshots, to)?.preferences,
+    ))
+}
+
+/// Applies the preferences of a snapshot. The caller records the revert as a
+/// new snapshot, so it can be undone in turn.
+pub fn revert<R: Runtime>(app: &AppHandle<R>, id: u64) -> Result<AppliedPreferences> {
+    let snapshots = load(app)?;
+    helper::apply_update_preferences(find(&snapshots, id)?.preferences.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::ChangeEffect;
+    use serde_json::Value;
+
+    fn preferences(api_port: u16) -> UpdateLeafPreferences {
+        UpdateLeafPreferences {
+            api_port,
+            ..helper::test_preferences()
+        }
+    }
+
+    fn port_change(old: u16, new: u16) -> PreferenceChange {
+        PreferenceChange {
+            field: "api_port".to_string(),
+            old: Value::from(old),
+            new: Value::from(new),
+            effect: ChangeEffect::Reload,
+        }
+    }
+
+    #[test]
+    fn first_record_keeps_the_baseline() {
+        let mut snapshots = Vec::new();
+        push_snapshot(
+            &mut snapshots,
+            &preferences(20000),
+            &[port_change(10001, 20000)],
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(snapshots.len(), 2);
+        assert!(snapshots[0].changed_fields.is_empty());
+        assert_eq!(snapshots[0].preferences, preferences(10001));
+        assert_eq!(snapshots[1].preferences, preferences(20000));
+    }
+
+    #[test]
+    fn trimming_keeps_the_baseline() {
+        let mut snapshots = Vec::new();
+        for port in 0..MAX_SNAPSHOTS as u16 + 5 {
+            let port = 20000 + port;
+            push_snapshot(
+                &mut snapshots,
+                &preferences(port),
+                &[port_change(port - 1, port)],
+                1,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(snapshots.len(), MAX_SNAPSHOTS);
+        assert_eq!(snapshots[0].id, 1);
+        assert_eq!(snapshots[0].preferences, preferences(19999));
+        assert_eq!(snapshots[1].id, 8);
+        assert_eq!(snapshots.last().unwrap().preferences, preferences(20054));
+    }
+}
@@ -0,0 +1,277 @@
+use crate::deeplink::{self, DeepLinkAction};
+use crate::{is_core_started, window_manager};
+use log::{error, info};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Runtime};
+
+const LEAFSUB_EXTENSION: &str = ".leafsub";
+const DEEP_LINK_PREFIX: &str = "leafvpn://";
+
+// The same intent can reach us twice, e.g. from argv and the deep-link plugin.
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Something the app was asked to do from outside.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Intent {
+    /// A `.leafsub` file to import, which needs a window to ask for the passphrase.
+    OpenFile {
+        path: String,
+    },
+    DeepLink {
+        url: String,
+    },
+    /// `--connect` or `--disconnect` on the command line.
+    Command {
+        action: DeepLinkAction,
+    },
+}
+
+impl Intent {
+    // Connecting and disconnecting need core, whether they were asked for on
+    // the command line or by a deep link.
+    fn needs_core(&self) -> bool {
+        let action = match self {
+            Intent::Command { action } => Some(action.clone()),
+            Intent::DeepLink { url } => deeplink::parse(url).ok(),
+            Intent::OpenFile { .. } => None,
+        };
+        matches!(
+            action,
+            Some(DeepLinkAction::Connect | DeepLinkAction::Disconnect)
+        )
+    }
+}
+
+#[derive(Default)]
+struct IntentQueue {
+    window_ready: bool,
+    pending: Vec<Intent>,
+    recent: Vec<(Intent, Instant)>,
+}
+
+impl IntentQueue {
+    fn is_duplicate(&mut self, intent: &Intent) -> bool {
+        let now = Instant::now();
+        self.recent
+            .retain(|(_, time)| now.duration_since(*time) < DUPLICATE_WINDOW);
+        if self.recent.iter().any(|(recent, _)| recent == intent) {
+            return true;
+        }
+        self.recent.push((intent.clone(), now));
+        false
+    }
+
+    // Everything waits for a window that can show it, and connecting or
+    // disconnecting also waits for core.
+    fn is_deliverable(&self, intent: &Intent, core_started: bool) -> bool {
+        self.window_ready && (core_started || !intent.needs_core())
+    }
+
+    fn push(&mut self, intent: Intent, core_started: bool) -> Option<Intent> {
+        if self.is_deliverable(&intent, core_started) {
+            return Some(intent);
+        }
+        self.pending.push(intent);
+        None
+    }
+
+    // Takes the queued intents that can be handled now, in order.
+    fn release(&mut self, core_started: bool) -> Vec<Intent> {
+        let (ready, waiting) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|intent| self.is_deliverable(intent, core_started));
+        self.pending = waiting;
+        ready
+    }
+}
+
+static QUEUE: Lazy<Mutex<IntentQueue>> = Lazy::new(|| Mutex::new(IntentQueue::default()));
+
+/// Reads the intents out of command line arguments, skipping the program name.
+/// Deep links are kept exactly as received, as their signatures cover the raw
+/// text.
+pub fn from_args(args: impl IntoIterator<Item = String>) -> Vec<Intent> {
+    args.into_iter()
+        .skip(1)
+        .filter_map(|arg| match arg.as_str() {
+            "--connect" => Some(Intent::Command {
+                action: DeepLinkAction::Connect,
+            }),
+            "--disconnect" => Some(Intent::Command {
+                action: DeepLinkAction::Disconnect,
+            }),
+            _ if arg.to_lowercase().starts_with(DEEP_LINK_PREFIX) => {
+                Some(Intent::DeepLink { url: arg })
+            }
+            _ if arg.to_lowercase().ends_with(LEAFSUB_EXTENSION) => {
+                Some(Intent::OpenFile { path: arg })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Deep links reported by the deep-link plugin, which only has them parsed.
+#[cfg(target_os = "macos")]
+pub fn from_urls(urls: impl IntoIterator<Item = String>) -> Vec<Intent> {
+    urls.into_iter()
+        .map(|url| Intent::DeepLink { url })
+        .collect()
+}
+
+fn deliver<R: Runtime>(app: &AppHandle<R>, intent: Intent) {
+    info!("Handling intent: {:?}", intent);
+    match intent {
+        Intent::OpenFile { .. } => {
+            tauri::async_runtime::block_on(window_manager::WindowManager::show_main_window(app));
+            let _ = app.emit("intent-event", intent);
+        }
+        Intent::DeepLink { url } => deeplink::handle_urls(app, &[url]),
+        Intent::Command { action } => {
+            if let Err(e) = deeplink::run(app, action.clone()) {
+                error!("Command {:?} failed: {}", action, e);
+            }
+        }
+    }
+}
+
+/// Handles `intents`, or queues them until a window called [`take_pending`]
+/// and, for connecting and disconnecting, until core has started.
+pub fn push<R: Runtime>(app: &AppHandle<R>, intents: Vec<Intent>) {
+    let mut deliverable = Vec::new();
+    {
+        let mut queue = QUEUE.lock();
+        for intent in intents {
+            if queue.is_duplicate(&intent) {
+                info!("Ignoring duplicate intent: {:?}", intent);
+                continue;
+            }
+            deliverable.extend(queue.push(intent, is_core_started()));
+        }
+    }
+
+    for intent in deliverable {
+        deliver(app, intent);
+    }
+}
+
+/// Marks the window as ready, handles the queued deep links and commands that
+/// can run now, and hands over the queued file opens.
+pub fn take_pending<R: Runtime>(app: &AppHandle<R>) -> Vec<Intent> {
+    let ready = {
+        let mut queue = QUEUE.lock();
+        queue.window_ready = true;
+        queue.release(is_core_started())
+    };
+
+    let (files, others): (Vec<Intent>, Vec<Intent>) = ready
+        .into_iter()
+        .partition(|intent| matches!(intent, Intent::OpenFile { .. }));
+    for intent in others {
+        deliver(app, intent);
+    }
+    files
+}
+
+/// Runs the intents that waited for core to start.
+pub fn core_started<R: Runtime>(app: &AppHandle<R>) {
+    let ready = QUEUE.lock().release(true);
+    for intent in ready {
+        deliver(app, intent);
+    }
+}
+
+/// Queues intents again while the page reloads, until it takes them anew.
+pub fn page_loading() {
+    QUEUE.lock().window_ready = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(action: DeepLinkAction) -> Intent {
+        Intent::Command { action }
+    }
+
+    fn link(url: &str) -> Intent {
+        Intent::DeepLink {
+            url: url.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_commands_links_and_files_from_args() {
+        let args = [
+            "leaf",
+            "--connect",
+            "leafvpn://connect?x=%2F",
+            "a.LEAFSUB",
+            "-v",
+        ];
+        let intents = from_args(args.map(str::to_string));
+        assert_eq!(
+            intents,
+            [
+                command(DeepLinkAction::Connect),
+                link("leafvpn://connect?x=%2F"),
+                Intent::OpenFile {
+                    path: "a.LEAFSUB".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn queues_everything_until_the_window_is_ready() {
+        let mut queue = IntentQueue::default();
+        let select = link("leafvpn://select?outbound=Proxy");
+        assert_eq!(queue.push(select.clone(), true), None);
+        assert_eq!(queue.push(command(DeepLinkAction::Connect), false), None);
+
+        queue.window_ready = true;
+        assert_eq!(queue.release(false), [select]);
+        assert_eq!(queue.release(true), [command(DeepLinkAction::Connect)]);
+        assert!(queue.pending.is_empty());
+    }
+
+    #[test]
+    fn commands_wait_for_core() {
+        let mut queue = IntentQueue {
+            window_ready: true,
+            ..Default::default()
+        };
+        assert_eq!(queue.push(command(DeepLinkAction::Disconnect), false), None);
+        let opened = Intent::OpenFile {
+            path: "a.leafsub".to_string(),
+        };
+        assert_eq!(queue.push(opened.clone(), false), Some(opened));
+        assert_eq!(queue.release(true).len(), 1);
+    }
+
+    #[test]
+    fn connect_links_wait_for_core_too() {
+        let mut queue = IntentQueue {
+            window_ready: true,
+            ..Default::default()
+        };
+        assert_eq!(queue.push(link("leafvpn://connect"), false), None);
+        assert_eq!(queue.push(link("leafvpn://disconnect"), false), None);
+        assert_eq!(
+            queue.release(true),
+            [link("leafvpn://connect"), link("leafvpn://disconnect")]
+        );
+    }
+
+    #[test]
+    fn ignores_repeats_within_a_short_window() {
+        let mut queue = IntentQueue::default();
+        assert!(!queue.is_duplicate(&link("leafvpn://connect")));
+        assert!(queue.is_duplicate(&link("leafvpn://connect")));
+        assert!(!queue.is_duplicate(&link("leafvpn://disconnect")));
+    }
+}
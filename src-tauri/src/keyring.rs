@@ -0,0 +1,243 @@
+use crate::{persistence, util};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::fs;
+use tauri::{AppHandle, Runtime};
+
+const KEYRING_FILE: &str = "keyring.json";
+const KEYRING_KEY: &str = "keys";
+
+/// Signing keys trusted out of the box, as `(id, public key)` pairs.
+const BUILTIN_KEYS: &[(&str, &str)] = &[("k1", "FjBD6zMrxVtHpWqzqsuFmT8uB7RZKmMuO94nT0N5LKo")];
+
+const ED25519_KEY_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyringEntry {
+    pub id: String,
+    pub public_key: String,
+    pub fingerprint: String,
+    pub added_at: u64,
+    pub revoked: bool,
+    /// Whether the key is trusted for `.leafsub` imports and signed deep links.
+    pub default: bool,
+    pub builtin: bool,
+}
+
+pub fn decode_public_key(public_key: &str) -> Result<Vec<u8>> {
+    let public_key = public_key.trim();
+    let bytes = URL_SAFE_NO_PAD
+        .decode(public_key.trim_end_matches('='))
+        .or_else(|_| STANDARD.decode(public_key))
+        .map_err(|_| anyhow!("public key is not valid base64"))?;
+
+    if bytes.len() != ED25519_KEY_LEN {
+        return Err(anyhow!(
+            "public key must be {} bytes, got {}",
+            ED25519_KEY_LEN,
+            bytes.len()
+        ));
+    }
+    Ok(bytes)
+}
+
+/// SHA-256 of the raw key, shown as the first 16 bytes in colon-separated hex.
+pub fn fingerprint(key: &[u8]) -> String {
+    Sha256::digest(key)[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn new_entry(id: &str, public_key: &str, builtin: bool) -> Result<KeyringEntry> {
+    let key = decode_public_key(public_key)?;
+    Ok(KeyringEntry {
+        id: id.to_string(),
+        public_key: public_key.trim().to_string(),
+        fingerprint: fingerprint(&key),
+        added_at: util::now(),
+        revoked: false,
+        default: true,
+        builtin,
+    })
+}
+
+fn load<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<KeyringEntry>> {
+    let mut entries: Vec<KeyringEntry> = persistence::load(app, KEYRING_FILE, KEYRING_KEY)?;
+
+    for (id, public_key) in BUILTIN_KEYS {
+        if !entries.iter().any(|entry| entry.id == *id) {
+            entries.push(new_entry(id, public_key, true)?);
+        }
+    }
+
+    Ok(entries)
+}
+
+fn save<R: Runtime>(app: &AppHandle<R>, entries: &[KeyringEntry]) -> Result<()> {
+    persistence::save(app, KEYRING_FILE, KEYRING_KEY, &entries)
+}
+
+pub fn list<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<KeyringEntry>> {
+    load(app)
+}
+
+fn insert(entries: &mut Vec<KeyringEntry>, id: &str, public_key: &str) -> Result<()> {
+    let id = id.trim();
+    if id.is_empty() {
+        return Err(anyhow!("key id must not be empty"));
+    }
+    if entries.iter().any(|entry| entry.id == id) {
+        return Err(anyhow!("key '{}' already exists", id));
+    }
+
+    let entry = new_entry(id, public_key, false)?;
+    if let Some(existing) = entries
+        .iter()
+        .find(|existing| existing.fingerprint == entry.fingerprint)
+    {
+        return Err(anyhow!("key is already present as '{}'", existing.id));
+    }
+
+    entries.push(entry);
+    Ok(())
+}
+
+// Reads the `(id, public key)` pairs of a key file, which holds either a
+// single base64 public key, named by `id`, or a keyring JSON object mapping
+// ids to public keys.
+fn parse_key_file(contents: &str, id: Option<String>) -> Result<Vec<(String, String)>> {
+    let Ok(document) = serde_json::from_str::<Value>(contents) else {
+        let id = id.ok_or_else(|| anyhow!("a key id is required"))?;
+        return Ok(vec![(id, contents.to_string())]);
+    };
+
+    let keys: Map<String, Value> = match document {
+        Value::Object(keys) => keys,
+        _ => return Err(anyhow!("a keyring file must be a JSON object")),
+    };
+    keys.into_iter()
+        .map(|(id, public_key)| match public_key {
+            Value::String(public_key) => Ok((id, public_key)),
+            _ => Err(anyhow!("key '{}' is not a string", id)),
+        })
+        .collect()
+}
+
+/// Adds keys from a file holding either a single base64 public key, which
+/// needs an `id`, or a keyring JSON object mapping ids to public keys.
+pub fn add_from_file<R: Runtime>(
+    app: &AppHandle<R>,
+    path: String,
+    id: Option<String>,
+) -> Result<Vec<KeyringEntry>> {
+    let contents = fs::read_to_string(path)?;
+    let mut entries = load(app)?;
+
+    for (id, public_key) in parse_key_file(&contents, id)? {
+        insert(&mut entries, &id, &public_key)?;
+    }
+
+    save(app, &entries)?;
+    Ok(entries)
+}
+
+pub fn revoke<R: Runtime>(app: &AppHandle<R>, id: String) -> Result<()> {
+    let mut entries = load(app)?;
+    let entry = entries
+        .iter_mut()
+        .find(|entry| entry.id == id)
+        .ok_or_else(|| anyhow!("key '{}' does not exist", id))?;
+
+    entry.revoked = true;
+    entry.default = false;
+    save(app, &entries)
+}
+
+pub fn set_default<R: Runtime>(app: &AppHandle<R>, ids: Vec<String>) -> Result<()> {
+    let mut entries = load(app)?;
+
+    if let Some(unknown) = ids.iter().find(|id| !entries.iter().any(|e| &e.id == *id)) {
+        return Err(anyhow!("key '{}' does not exist", unknown));
+    }
+
+    for entry in entries.iter_mut() {
+        let selected = ids.contains(&entry.id);
+        if selected && entry.revoked {
+            return Err(anyhow!("key '{}' is revoked", entry.id));
+        }
+        entry.default = selected;
+    }
+
+    save(app, &entries)
+}
+
+/// Keys that are neither revoked nor excluded from the defaults.
+pub fn trusted_keys<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<KeyringEntry>> {
+    Ok(load(app)?
+        .into_iter()
+        .filter(|entry| entry.default && !entry.revoked)
+        .collect())
+}
+
+/// Builds the keyring JSON expected by `leaf_sdk_desktop` from the trusted keys.
+pub fn trusted_keyring_json<R: Runtime>(app: &AppHandle<R>) -> Result<String> {
+    let keys: Map<String, Value> = trusted_keys(app)?
+        .into_iter()
+        .map(|entry| (entry.id, Value::String(entry.public_key)))
+        .collect();
+
+    if keys.is_empty() {
+        return Err(anyhow!("no trusted keys in the keyring"));
+    }
+    Ok(serde_json::to_string(&keys)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "FjBD6zMrxVtHpWqzqsuFmT8uB7RZKmMuO94nT0N5LKo";
+
+    #[test]
+    fn decodes_both_base64_alphabets() {
+        let key = decode_public_key(KEY).unwrap();
+        assert_eq!(decode_public_key(&STANDARD.encode(&key)).unwrap(), key);
+        assert!(decode_public_key("AAAA").is_err());
+        assert_eq!(fingerprint(&key).split(':').count(), 16);
+    }
+
+    #[test]
+    fn parses_single_keys_and_keyring_objects() {
+        assert_eq!(
+            parse_key_file(KEY, Some("team".to_string())).unwrap(),
+            [("team".to_string(), KEY.to_string())]
+        );
+        assert!(parse_key_file(KEY, None).is_err());
+
+        let keyring = format!(r#"{{ "team": "{}" }}"#, KEY);
+        assert_eq!(parse_key_file(&keyring, None).unwrap().len(), 1);
+        assert!(parse_key_file(r#"{ "team": 1 }"#, None).is_err());
+    }
+
+    #[test]
+    fn rejects_json_that_is_not_an_object() {
+        let list = format!(r#"["{}"]"#, KEY);
+        assert!(parse_key_file(&list, Some("team".to_string())).is_err());
+        let string = format!(r#""{}""#, KEY);
+        assert!(parse_key_file(&string, Some("team".to_string())).is_err());
+    }
+
+    #[test]
+    fn refuses_duplicate_ids_and_keys() {
+        let mut entries = vec![new_entry("k1", KEY, true).unwrap()];
+        assert!(insert(&mut entries, "k1", KEY).is_err());
+        assert!(insert(&mut entries, "team", KEY).is_err());
+        assert!(insert(&mut entries, " ", KEY).is_err());
+    }
+}
@@ -0,0 +1,456 @@
+use crate::dns::{self, DnsSettings};
+use crate::routing::{self, RoutingRule};
+use crate::{geodata, persistence, subscriptions, util};
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use tauri::{AppHandle, Runtime};
+
+// The SDK writes the fetched subscription config next to leaf's assets and has
+// no API that returns its path.
+const CONFIG_FILE: &str = "config.json";
+
+const LEAF_CONFIG_FILE: &str = "leaf_config.json";
+const BASE_KEY: &str = "base";
+const VERSIONS_KEY: &str = "versions";
+const INSTALLED_KEY: &str = "installed";
+const MAX_VERSIONS: usize = 5;
+
+// The version to roll back to while leaf has not run with the config of the
+// latest update yet.
+static UNCONFIRMED: Lazy<Mutex<Option<u64>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Outbound {
+    pub tag: String,
+    pub protocol: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ConfigVersion {
+    pub id: u64,
+    pub fetched_at: u64,
+    pub client_id: Option<String>,
+    pub outbounds: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct StoredVersion {
+    #[serde(flatten)]
+    version: ConfigVersion,
+    config: Value,
+}
+
+/// A fetched config that was installed, along with the one it replaced.
+pub struct Adopted {
+    pub previous: Option<Value>,
+    pub config: Value,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ConfigHistory {
+    pub versions: Vec<ConfigVersion>,
+    pub installed: Option<u64>,
+}
+
+pub fn path() -> Result<PathBuf> {
+    geodata::asset_path(CONFIG_FILE)
+}
+
+pub fn read() -> Result<Value> {
+    let path = path()?;
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| anyhow!("failed to read {}: {}", path.display(), e))?;
+    let config: Value = serde_json::from_str(&contents)?;
+    if !config.is_object() {
+        return Err(anyhow!("{} is not a JSON object", path.display()));
+    }
+    Ok(config)
+}
+
+// Replaces the config in one step so leaf never reads a partial file.
+fn write(config: &Value) -> Result<()> {
+    let path = path()?;
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, serde_json::to_string_pretty(config)?)?;
+    fs::rename(&temp, &path)?;
+    Ok(())
+}
+
+/// The outbounds a config defines, in order.
+pub fn outbounds(config: &Value) -> Vec<Outbound> {
+    config["outbounds"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|outbound| {
+            Some(Outbound {
+                tag: outbound["tag"].as_str()?.to_string(),
+                protocol: outbound["protocol"].as_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The config as fetched from the subscription, before our overrides were
+/// added. The first call adopts the config leaf currently reads.
+pub fn base<R: Runtime>(app: &AppHandle<R>) -> Result<Option<Value>> {
+    let base: Option<Value> = persistence::load(app, LEAF_CONFIG_FILE, BASE_KEY)?;
+    if base.is_some() {
+        return Ok(base);
+    }
+
+    match read() {
+        Ok(config) => {
+            persistence::save(app, LEAF_CONFIG_FILE, BASE_KEY, &Some(config.clone()))?;
+            Ok(Some(config))
+        }
+        Err(e) => {
+            log::warn!("No leaf config to adopt yet: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+/// Returns `base` with the custom routing rules in front of its own, so they
+/// take precedence, and with the DNS settings merged into its `dns` object.
+/// Rules the outbounds of `base` cannot serve are skipped.
+pub fn compose(base: &Value, rules: &[RoutingRule], dns: &DnsSettings) -> Result<Value> {
+    let mut config = base.clone();
+    let custom = routing::usable_leaf_rules(rules, &outbounds(base));
+
+    let object = config
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("config is not a JSON object"))?;
+    let router = object.entry("router").or_insert_with(|| json!({}));
+    if !router.is_object() {
+        return Err(anyhow!("the router of the config is not an object"));
+    }
+    let mut rules = custom;
+    rules.extend(router["rules"].as_array().cloned().unwrap_or_default());
+    router["rules"] = Value::Array(rules);
+
+    if *dns != DnsSettings::default() {
+        let section = object.entry("dns").or_insert_with(|| json!({}));
+        let section = section
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("the dns of the config is not an object"))?;
+        dns::merge_into(dns, section);
+    }
+
+    Ok(config)
+}
+
+fn compose_current<R: Runtime>(app: &AppHandle<R>, base: &Value) -> Result<Value> {
+    compose(base, &routing::get_rules(app)?, &dns::get_settings(app)?)
+}
+
+/// Writes the base config with the current overrides to where leaf reads it.
+/// Returns `false` when there is no config yet.
+pub fn apply<R: Runtime>(app: &AppHandle<R>) -> Result<bool> {
+    let Some(base) = base(app)? else {
+        return Ok(false);
+    };
+    write(&compose_current(app, &base)?)?;
+    Ok(true)
+}
+
+// The checks that need no leaf, run before a config is written anywhere.
+fn check(config: &Value) -> Result<()> {
+    match config["outbounds"].as_array() {
+        Some(outbounds) if !outbounds.is_empty() => Ok(()),
+        _ => Err(anyhow!("the config has no outbounds")),
+    }
+}
+
+// Has leaf check the config where it reads it; the SDK tests no other path.
+fn test() -> Result<()> {
+    leaf_sdk_desktop::test_config().map_err(|e| anyhow!("{}", e))
+}
+
+// Puts the installed config back after a rejected one was written. Without
+// one, the rejected config is removed so that leaf cannot load it.
+fn revert<R: Runtime>(app: &AppHandle<R>, installed: Option<&Value>) -> Result<()> {
+    match installed {
+        Some(installed) => write(&compose_current(app, installed)?),
+        None => match fs::remove_file(path()?) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        },
+    }
+}
+
+// Checks `base`, then writes it with the overrides and has leaf test it. A
+// rejected config is reverted right away, before anything reloads leaf.
+fn install<R: Runtime>(app: &AppHandle<R>, base: &Value) -> Result<()> {
+    check(base)?;
+    let installed: Option<Value> = persistence::load(app, LEAF_CONFIG_FILE, BASE_KEY)?;
+
+    let checked = write(&compose_current(app, base)?).and_then(|()| test());
+    if let Err(e) = checked {
+        revert(app, installed.as_ref())?;
+        return Err(anyhow!("leaf rejected the config: {}", e));
+    }
+
+    persistence::save(app, LEAF_CONFIG_FILE, BASE_KEY, &Some(base.clone()))
+}
+
+fn stored_versions<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<StoredVersion>> {
+    persistence::load(app, LEAF_CONFIG_FILE, VERSIONS_KEY)
+}
+
+fn installed_version<R: Runtime>(app: &AppHandle<R>) -> Result<Option<u64>> {
+    persistence::load(app, LEAF_CONFIG_FILE, INSTALLED_KEY)
+}
+
+// Appends a version, dropping the oldest ones beyond `MAX_VERSIONS`, and
+// returns its id.
+fn push_version(
+    versions: &mut Vec<StoredVersion>,
+    config: Value,
+    client_id: Option<String>,
+    now: u64,
+) -> u64 {
+    let id = versions.last().map_or(1, |stored| stored.version.id + 1);
+    versions.push(StoredVersion {
+        version: ConfigVersion {
+            id,
+            fetched_at: now,
+            client_id,
+            outbounds: outbounds(&config).len(),
+        },
+        config,
+    });
+    if versions.len() > MAX_VERSIONS {
+        let excess = versions.len() - MAX_VERSIONS;
+        versions.drain(..excess);
+    }
+    id
+}
+
+/// Validates the config the SDK just fetched and installs it with the
+/// overrides, or puts the installed config back when leaf rejects it. An
+/// accepted config is kept as a version to roll back to.
+///
+/// The SDK writes the fetched config where leaf reads it before it reports
+/// success, and has no way to check it first. With `auto_reload` on, leaf may
+/// therefore pick up a config that is rejected here; it is reverted as soon as
+/// the check fails, before the overrides are written.
+pub fn adopt_fetched<R: Runtime>(app: &AppHandle<R>) -> Result<Adopted> {
+    let previous: Option<Value> = persistence::load(app, LEAF_CONFIG_FILE, BASE_KEY)?;
+    let fetched = read().and_then(|fetched| {
+        check(&fetched)?;
+        test()?;
+        Ok(fetched)
+    });
+    let fetched = match fetched {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            revert(app, previous.as_ref())?;
+            return Err(anyhow!("leaf rejected the config: {}", e));
+        }
+    };
+    install(app, &fetched)?;
+
+    let client_id = subscriptions::current_client_id()?;
+
+    let installed = installed_version(app)?;
+    let mut versions = stored_versions(app)?;
+    let id = push_version(&mut versions, fetched.clone(), client_id, util::now());
+    persistence::save(app, LEAF_CONFIG_FILE, VERSIONS_KEY, &versions)?;
+    persistence::save(app, LEAF_CONFIG_FILE, INSTALLED_KEY, &Some(id))?;
+
+    *UNCONFIRMED.lock() = installed;
+    Ok(Adopted {
+        previous,
+        config: fetched,
+    })
+}
+
+/// The retained versions, newest first.
+pub fn history<R: Runtime>(app: &AppHandle<R>) -> Result<ConfigHistory> {
+    let mut versions: Vec<ConfigVersion> = stored_versions(app)?
+        .into_iter()
+        .map(|stored| stored.version)
+        .collect();
+    versions.reverse();
+
+    Ok(ConfigHistory {
+        versions,
+        installed: installed_version(app)?,
+    })
+}
+
+/// Installs the retained version `id`.
+pub fn roll_back<R: Runtime>(app: &AppHandle<R>, id: u64) -> Result<()> {
+    let stored = stored_versions(app)?
+        .into_iter()
+        .find(|stored| stored.version.id == id)
+        .ok_or_else(|| anyhow!("config version {} is not retained", id))?;
+
+    install(app, &stored.config)?;
+    persistence::save(app, LEAF_CONFIG_FILE, INSTALLED_KEY, &Some(id))?;
+    UNCONFIRMED.lock().take();
+    Ok(())
+}
+
+/// Marks the installed config as working once leaf runs with it.
+pub fn confirm() {
+    UNCONFIRMED.lock().take();
+}
+
+/// Rolls back to the version installed before the latest update when leaf
+/// failed before running with it. Returns the version rolled back to.
+pub fn roll_back_unconfirmed<R: Runtime>(app: &AppHandle<R>) -> Result<Option<u64>> {
+    let Some(id) = UNCONFIRMED.lock().take() else {
+        return Ok(None);
+    };
+    roll_back(app, id)?;
+    Ok(Some(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::{RuleAction, RuleKind};
+
+    fn config() -> Value {
+        json!({
+            "outbounds": [
+                { "tag": "Proxy", "protocol": "select" },
+                { "tag": "Freedom", "protocol": "direct" },
+                { "tag": "Block", "protocol": "drop" },
+                { "protocol": "trojan" }
+            ],
+            "router": { "rules": [{ "geoip": ["cn"], "target": "Freedom" }] }
+        })
+    }
+
+    fn rule(kind: RuleKind, value: &str, action: RuleAction) -> RoutingRule {
+        RoutingRule {
+            kind,
+            value: value.to_string(),
+            action,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn requires_outbounds() {
+        assert!(check(&config()).is_ok());
+        assert!(check(&json!({ "outbounds": [] })).is_err());
+        assert!(check(&json!({ "router": {} })).is_err());
+    }
+
+    #[test]
+    fn lists_tagged_outbounds() {
+        let tags: Vec<String> = outbounds(&config()).into_iter().map(|o| o.tag).collect();
+        assert_eq!(tags, ["Proxy", "Freedom", "Block"]);
+    }
+
+    #[test]
+    fn puts_custom_rules_first_with_config_tags() {
+        let rules = [
+            rule(RuleKind::DomainSuffix, "corp.example", RuleAction::Direct),
+            rule(RuleKind::IpCidr, "10.0.0.0/8", RuleAction::Reject),
+            rule(
+                RuleKind::Domain,
+                "example.com",
+                RuleAction::Proxy {
+                    outbound: "Proxy".to_string(),
+                },
+            ),
+        ];
+
+        let config = compose(&config(), &rules, &DnsSettings::default()).unwrap();
+        assert_eq!(
+            config["router"]["rules"],
+            json!([
+                { "domainSuffix": ["corp.example"], "target": "Freedom" },
+                { "ip": ["10.0.0.0/8"], "target": "Block" },
+                { "domain": ["example.com"], "target": "Proxy" },
+                { "geoip": ["cn"], "target": "Freedom" }
+            ])
+        );
+    }
+
+    #[test]
+    fn adds_a_router_when_the_config_has_none() {
+        let base = json!({ "outbounds": [{ "tag": "Direct", "protocol": "direct" }] });
+        let rules = [rule(RuleKind::Network, "udp", RuleAction::Direct)];
+        let config = compose(&base, &rules, &DnsSettings::default()).unwrap();
+        assert_eq!(
+            config["router"]["rules"],
+            json!([{ "network": ["udp"], "target": "Direct" }])
+        );
+    }
+
+    #[test]
+    fn skips_rules_for_outbounds_the_config_lacks() {
+        let base = json!({ "outbounds": [{ "tag": "Direct", "protocol": "direct" }] });
+        let reject = [rule(RuleKind::Domain, "ads.example", RuleAction::Reject)];
+        let config = compose(&base, &reject, &DnsSettings::default()).unwrap();
+        assert_eq!(config["router"]["rules"], json!([]));
+
+        let proxy = [rule(
+            RuleKind::Domain,
+            "example.com",
+            RuleAction::Proxy {
+                outbound: "Missing".to_string(),
+            },
+        )];
+        let config = compose(&base, &proxy, &DnsSettings::default()).unwrap();
+        assert_eq!(config["router"]["rules"], json!([]));
+    }
+
+    #[test]
+    fn merges_dns_settings_into_the_config() {
+        let mut base = config();
+        base["dns"] = json!({
+            "servers": ["223.5.5.5"],
+            "hosts": { "router.lan": ["192.168.1.1"], "nas.lan": ["192.168.1.2"] }
+        });
+        let settings = DnsSettings {
+            upstreams: vec![],
+            hosts: vec![dns::HostOverride {
+                domain: "nas.lan".to_string(),
+                ips: vec!["10.0.0.2".to_string()],
+            }],
+        };
+
+        let composed = compose(&base, &[], &settings).unwrap();
+        assert_eq!(
+            composed["dns"],
+            json!({
+                "servers": ["223.5.5.5"],
+                "hosts": { "router.lan": ["192.168.1.1"], "nas.lan": ["10.0.0.2"] }
+            })
+        );
+
+        let settings = DnsSettings {
+            upstreams: vec![dns::DnsUpstream {
+                address: "udp://1.1.1.1:53".to_string(),
+            }],
+            ..Default::default()
+        };
+        let composed = compose(&config(), &[], &settings).unwrap();
+        assert_eq!(composed["dns"], json!({ "servers": ["1.1.1.1:53"] }));
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_versions() {
+        let mut versions = Vec::new();
+        for _ in 0..MAX_VERSIONS + 2 {
+            push_version(&mut versions, config(), None, 1);
+        }
+
+        let ids: Vec<u64> = versions.iter().map(|stored| stored.version.id).collect();
+        assert_eq!(ids, [3, 4, 5, 6, 7]);
+        assert_eq!(versions[0].version.outbounds, 3);
+    }
+}
@@ -8,15 +8,37 @@ use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::env;
 use std::process::Command;
+use std::thread;
+use tauri::webview::PageLoadEvent;
 use tauri::{AppHandle, Emitter, Manager, RunEvent, Runtime, Window};
 use tauri_plugin_shell::ShellExt;
 
 #[cfg(windows)]
 use log::error;
 
+mod deeplink;
+mod dns;
+mod drop_folder;
+mod fetch_options;
+mod geodata;
 mod helper;
+mod history;
+mod intents;
+mod keyring;
+mod leaf_config;
+mod persistence;
+mod policy;
+mod preferences_file;
+mod profiles;
+mod qr;
+mod quota;
+mod routing;
+mod scheduler;
+mod subscription_diff;
+mod subscriptions;
 mod tray;
 mod tray_icon_manager;
+mod util;
 mod window_manager;
 
 #[cfg(unix)]
@@ -31,7 +53,6 @@ pub static LATEST_SUBSCRIPTION_STATE: Lazy<Mutex<Option<SubscriptionState>>> =
     Lazy::new(|| Mutex::new(None));
 pub static FILE_WATCHER: Lazy<Mutex<Option<notify::RecommendedWatcher>>> =
     Lazy::new(|| Mutex::new(None));
-pub static PENDING_LEAFSUB_PATHS: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(Vec::new()));
 
 fn leaf_sidecar_program<R: Runtime>(app: &AppHandle<R>) -> String {
     let sidecar = app.shell().sidecar("leaf-ipc").unwrap();
@@ -49,6 +70,9 @@ pub struct FileWatchEvent {
 fn core_callback<R: Runtime>(window: Window<R>, state: CoreState) {
     info!("Core state: {:?}", state);
     *LATEST_CORE_STATE.lock() = Some(state.clone());
+    if matches!(state, CoreState::STARTED) {
+        intents::core_started(window.app_handle());
+    }
     window.emit("core-event", state).unwrap();
 
     // Update tray icon based on new state
@@ -58,36 +82,218 @@ fn core_callback<R: Runtime>(window: Window<R>, state: CoreState) {
 fn leaf_callback<R: Runtime>(window: Window<R>, state: LeafState) {
     info!("Leaf state: {:?}", state);
     *LATEST_LEAF_STATE.lock() = Some(state.clone());
+
+    match &state {
+        LeafState::STARTED | LeafState::RELOADED => leaf_config::confirm(),
+        LeafState::ERROR { .. } => roll_back_failed_update(window.clone()),
+        _ => {}
+    }
+
     window.emit("leaf-event", state).unwrap();
 
     // Update tray icon based on new state
     tray_icon_manager::update_tray_icon(window.app_handle());
 }
 
-fn subscription_state<R: Runtime>(window: Window<R>, state: SubscriptionState) {
+// Puts back the config that worked before the latest subscription update when
+// leaf fails before running with the new one, and starts leaf again.
+fn roll_back_failed_update<R: Runtime>(window: Window<R>) {
+    thread::spawn(move || {
+        let id = match leaf_config::roll_back_unconfirmed(window.app_handle()) {
+            Ok(Some(id)) => id,
+            Ok(None) => return,
+            Err(e) => {
+                log::error!("Failed to roll back the leaf config: {}", e);
+                return;
+            }
+        };
+
+        log::warn!(
+            "Leaf failed after a subscription update, rolled back to config {}",
+            id
+        );
+        window.emit("config-rollback-event", id).unwrap();
+
+        let restarted = match reload_leaf_if_running(window.clone()) {
+            Ok(true) => Ok(()),
+            Ok(false) => leaf_sdk_desktop::run_leaf(move |state| {
+                leaf_callback(window.clone(), state.clone());
+            })
+            .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = restarted {
+            log::error!("Failed to restart leaf after rolling back: {}", e);
+        }
+    });
+}
+
+fn reload_leaf_if_running<R: Runtime>(window: Window<R>) -> Result<bool, String> {
+    if !leaf_sdk_desktop::is_leaf_running().unwrap_or(false) {
+        return Ok(false);
+    }
+
+    leaf_sdk_desktop::reload_leaf(move |state| {
+        leaf_callback(window.clone(), state.clone());
+    })
+    .map_err(|e| format!("reload_leaf failed: {}", e))?;
+
+    Ok(true)
+}
+
+/// Rewrites the leaf config with the current routing rules and DNS settings and
+/// reloads leaf so it picks them up.
+fn apply_leaf_config<R: Runtime>(window: Window<R>) -> Result<bool, String> {
+    let applied = leaf_config::apply(window.app_handle())
+        .map_err(|e| format!("failed to write the leaf config: {}", e))?;
+    if !applied {
+        return Ok(false);
+    }
+    reload_leaf_if_running(window)
+}
+
+/// Like [`reload_leaf_if_running`], for background tasks that have no window.
+fn reload_leaf_from_app<R: Runtime>(app: &AppHandle<R>) -> Result<bool, String> {
+    let window = window_manager::WindowManager::get_main_window(app)
+        .map(|window| window.as_ref().window())
+        .ok_or_else(|| "main window is not available".to_string())?;
+    reload_leaf_if_running(window)
+}
+
+fn is_core_started() -> bool {
+    matches!(*LATEST_CORE_STATE.lock(), Some(CoreState::STARTED))
+}
+
+/// Reloads leaf when a preference change needs it and reports whether the
+/// running core has to be restarted for the rest to take effect.
+fn apply_preference_effects<R: Runtime>(
+    window: Window<R>,
+    mut update: helper::PreferencesUpdate,
+) -> Result<helper::PreferencesUpdate, String> {
+    if let Some(applied) = &update.applied {
+        if let Err(e) = history::record(window.app_handle(), applied, &update.changes) {
+            log::error!("Failed to record preference history: {}", e);
+        }
+    }
+
+    // A restart-class change still reloads leaf so that the other changes
+    // made along with it apply right away.
+    let effect = update.effect();
+    if effect >= Some(helper::ChangeEffect::Reload) {
+        update.reloaded = reload_leaf_if_running(window.clone())?;
+    }
+    if effect == Some(helper::ChangeEffect::Restart) {
+        update.restart_required = is_core_started();
+    }
+
+    info!(
+        "Preferences applied: reloaded={}, restart_required={}",
+        update.reloaded, update.restart_required
+    );
+    window.emit("preferences-event", update.clone()).unwrap();
+
+    Ok(update)
+}
+
+fn subscription_state<R: Runtime>(app: &AppHandle<R>, state: SubscriptionState) {
+    handle_subscription_state(app, state, None, |_| {});
+}
+
+fn fetched_subscription_state<R: Runtime>(
+    app: &AppHandle<R>,
+    state: SubscriptionState,
+    options: fetch_options::EffectiveFetchOptions,
+) {
+    handle_subscription_state(app, state, Some(options), |_| {});
+}
+
+/// Reports a state of a subscription update, along with the fetch options it
+/// ran with, and passes it on to `done`. A fetched config only counts as a
+/// success once leaf accepts it, which is checked on another thread so that
+/// the SDK's callback is not held up.
+fn handle_subscription_state<R, F>(
+    app: &AppHandle<R>,
+    state: SubscriptionState,
+    options: Option<fetch_options::EffectiveFetchOptions>,
+    done: F,
+) where
+    R: Runtime,
+    F: FnOnce(&SubscriptionState) + Send + 'static,
+{
+    if !matches!(state, SubscriptionState::SUCCESS) {
+        report_subscription_state(app, &state, options);
+        done(&state);
+        return;
+    }
+
+    let app = app.clone();
+    thread::spawn(move || {
+        let adopted = leaf_config::adopt_fetched(&app);
+        let state = match &adopted {
+            Ok(_) => SubscriptionState::SUCCESS,
+            Err(e) => SubscriptionState::ERROR {
+                error: format!("fetched config was not installed: {}", e),
+            },
+        };
+        report_subscription_state(&app, &state, options);
+        if let Ok(adopted) = &adopted {
+            subscription_diff::on_update(&app, adopted);
+        }
+        done(&state);
+    });
+}
+
+fn report_subscription_state<R: Runtime>(
+    app: &AppHandle<R>,
+    state: &SubscriptionState,
+    options: Option<fetch_options::EffectiveFetchOptions>,
+) {
     info!("Subscription state: {:?}", state);
     *LATEST_SUBSCRIPTION_STATE.lock() = Some(state.clone());
-    window.emit("subscription-event", state).unwrap();
-}
 
-fn emit_leafsub_paths<R: Runtime>(app: &AppHandle<R>, paths: &[String]) {
-    let window = window_manager::WindowManager::get_main_window(app);
-    if let Some(win) = window {
-        tauri::async_runtime::block_on(window_manager::WindowManager::show_main_window(app));
-        for path in paths {
-            if path.to_lowercase().ends_with(".leafsub") {
-                PENDING_LEAFSUB_PATHS.lock().push(path.clone());
-                let _ = win.emit("file-opened", path.clone());
-            }
+    let mut payload = serde_json::to_value(state).unwrap();
+
+    let outcome = match state {
+        SubscriptionState::SUCCESS => Some(None),
+        SubscriptionState::ERROR { error } => Some(Some(error.to_string())),
+        _ => None,
+    };
+    if let Some(error) = outcome {
+        let succeeded = error.is_none();
+        if let Err(e) = subscriptions::record_update(app, error) {
+            log::error!("Failed to record subscription update: {}", e);
         }
-    } else {
-        let mut pending = PENDING_LEAFSUB_PATHS.lock();
-        for path in paths {
-            if path.to_lowercase().ends_with(".leafsub") {
-                pending.push(path.clone());
+        if succeeded {
+            if let Err(e) = quota::check(app) {
+                log::error!("Subscription quota check failed: {}", e);
             }
         }
+
+        if let (Some(options), Some(object)) = (options, payload.as_object_mut()) {
+            object.insert("fetch_options".to_string(), serde_json::json!(options));
+        }
     }
+
+    app.emit("subscription-event", payload).unwrap();
+}
+
+/// Installs the subscription pinned by policy when another one is configured.
+fn install_pinned_subscription<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let Some(pinned) = policy::pinned_client_id() else {
+        return Ok(());
+    };
+    let current = subscriptions::current_client_id().map_err(|e| e.to_string())?;
+    if current.as_deref() == Some(pinned) {
+        return Ok(());
+    }
+    policy::check_update(false)?;
+
+    info!("Installing the subscription pinned by policy");
+    let handle = app.clone();
+    fetch_options::update_subscription(app, pinned.to_string(), move |state, options| {
+        fetched_subscription_state(&handle, state, options);
+    })
+    .map_err(|e| e.to_string())
 }
 
 fn file_watch_callback<R: Runtime>(window: Window<R>, event: FileWatchEvent) {
@@ -171,38 +377,285 @@ fn stop_leaf<R: Runtime>(_app: AppHandle<R>, window: Window<R>) {
 }
 
 #[tauri::command]
-fn auto_update_subscription<R: Runtime>(_app: AppHandle<R>, window: Window<R>) {
-    leaf_sdk_desktop::auto_update_subscription(move |state| {
-        subscription_state(window.clone(), state.clone());
-    });
+fn auto_update_subscription<R: Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+) -> Result<(), String> {
+    policy::check_update(true)?;
+
+    let client_id = subscriptions::current_client_id()
+        .map_err(|e| format!("auto_update_subscription failed: {}", e))?
+        .ok_or("auto_update_subscription failed: no subscription is configured")?;
+    fetch_options::update_subscription(&app, client_id, move |state, options| {
+        fetched_subscription_state(window.app_handle(), state, options);
+    })
+    .map_err(|e| format!("auto_update_subscription failed: {}", e))
 }
 
 #[tauri::command]
-fn update_subscription<R: Runtime>(_app: AppHandle<R>, window: Window<R>, client_id: String) {
-    // Pass `None` for tls and fragment to let the library auto-select the best options.
-    leaf_sdk_desktop::update_subscription(
-        None,
-        None,
-        client_id,
-        None,
-        None,
-        move |state: SubscriptionState| {
-            subscription_state(window.clone(), state.clone());
-        },
-    );
+fn update_subscription<R: Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    client_id: String,
+) -> Result<(), String> {
+    policy::check_client_id(&client_id)?;
+    policy::check_update(false)?;
+
+    fetch_options::update_subscription(&app, client_id, move |state, options| {
+        fetched_subscription_state(window.app_handle(), state, options);
+    })
+    .map_err(|e| format!("update_subscription failed: {}", e))
 }
 
 #[tauri::command]
-fn import_offline_subscription<R: Runtime>(
+async fn import_qr_code<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    path: Option<String>,
+) -> Result<String, String> {
+    qr::import(&app, path)
+        .await
+        .map_err(|e| format!("import_qr_code failed: {}", e))
+}
+
+#[tauri::command]
+fn get_share_qr_code<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    never_expires: Option<bool>,
+) -> Result<qr::ShareQrCode, String> {
+    qr::share(&app, never_expires.unwrap_or(false))
+        .map_err(|e| format!("get_share_qr_code failed: {}", e))
+}
+
+#[tauri::command]
+fn get_subscription_fetch_options<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<fetch_options::FetchOptions, String> {
+    fetch_options::get(&app).map_err(|e| format!("get_subscription_fetch_options failed: {}", e))
+}
+
+#[tauri::command]
+fn set_subscription_fetch_options<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    options: fetch_options::FetchOptions,
+) -> Result<(), String> {
+    fetch_options::set(&app, options)
+        .map_err(|e| format!("set_subscription_fetch_options failed: {}", e))
+}
+
+#[tauri::command]
+fn get_subscription_schedule<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<scheduler::SchedulerSettings, String> {
+    scheduler::get_settings(&app).map_err(|e| format!("get_subscription_schedule failed: {}", e))
+}
+
+#[tauri::command]
+fn set_subscription_schedule<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    enabled: bool,
+    interval_minutes: u64,
+    jitter_minutes: u64,
+) -> Result<scheduler::SchedulerSettings, String> {
+    scheduler::set_settings(&app, enabled, interval_minutes, jitter_minutes)
+        .map_err(|e| format!("set_subscription_schedule failed: {}", e))
+}
+
+#[tauri::command]
+fn get_subscription_status<R: Runtime>(
     _app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<quota::SubscriptionStatus, String> {
+    quota::get_status().map_err(|e| format!("get_subscription_status failed: {}", e))
+}
+
+#[tauri::command]
+fn get_quota_notifications<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<quota::QuotaSettings, String> {
+    quota::get_settings(&app).map_err(|e| format!("get_quota_notifications failed: {}", e))
+}
+
+#[tauri::command]
+fn set_quota_notifications<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    enabled: bool,
+    traffic_thresholds: Vec<u8>,
+    expiry_days: Vec<u32>,
+) -> Result<quota::QuotaSettings, String> {
+    quota::set_settings(&app, enabled, traffic_thresholds, expiry_days)
+        .map_err(|e| format!("set_quota_notifications failed: {}", e))
+}
+
+#[tauri::command]
+fn list_subscriptions<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<subscriptions::SubscriptionRegistry, String> {
+    subscriptions::list(&app).map_err(|e| format!("list_subscriptions failed: {}", e))
+}
+
+#[tauri::command]
+fn add_subscription<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    name: String,
+    client_id: String,
+) -> Result<(), String> {
+    subscriptions::add(&app, name, client_id).map_err(|e| format!("add_subscription failed: {}", e))
+}
+
+#[tauri::command]
+fn remove_subscription<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    name: String,
+) -> Result<(), String> {
+    subscriptions::remove(&app, name).map_err(|e| format!("remove_subscription failed: {}", e))
+}
+
+#[tauri::command]
+fn list_subscription_diffs<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<Vec<subscription_diff::SubscriptionDiff>, String> {
+    subscription_diff::list(&app).map_err(|e| format!("list_subscription_diffs failed: {}", e))
+}
+
+#[tauri::command]
+fn list_config_versions<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<leaf_config::ConfigHistory, String> {
+    leaf_config::history(&app).map_err(|e| format!("list_config_versions failed: {}", e))
+}
+
+#[tauri::command]
+fn roll_back_config<R: Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    id: u64,
+) -> Result<(), String> {
+    leaf_config::roll_back(&app, id).map_err(|e| format!("roll_back_config failed: {}", e))?;
+    reload_leaf_if_running(window)?;
+    Ok(())
+}
+
+#[tauri::command]
+fn switch_subscription<R: Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    name: String,
+) -> Result<(), String> {
+    let client_id = subscriptions::client_id(&app, &name)
+        .map_err(|e| format!("switch_subscription failed: {}", e))?;
+    policy::check_client_id(&client_id)?;
+    policy::check_update(false)?;
+
+    fetch_options::update_subscription(&app, client_id, move |state, options| {
+        let (window, name) = (window.clone(), name.clone());
+        // The SDK only takes the new client id with a successful fetch, so a
+        // failed one leaves the previous subscription active.
+        handle_subscription_state(window.app_handle(), state, Some(options), move |state| {
+            match state {
+                SubscriptionState::SUCCESS => {}
+                SubscriptionState::ERROR { error } => {
+                    log::error!("Failed to switch to subscription '{}': {}", name, error);
+                    return;
+                }
+                _ => return,
+            }
+            if let Err(e) = subscriptions::set_active(window.app_handle(), &name) {
+                log::error!("Failed to switch to subscription '{}': {}", name, e);
+                return;
+            }
+            if let Err(e) = reload_leaf_if_running(window) {
+                log::error!("Failed to reload leaf after switching subscription: {}", e);
+            }
+        });
+    })
+    .map_err(|e| format!("switch_subscription failed: {}", e))
+}
+
+#[tauri::command]
+fn import_offline_subscription<R: Runtime>(
+    app: AppHandle<R>,
     window: Window<R>,
     path: String,
     passphrase: Option<String>,
-    keyring_json: String,
-) {
+) -> Result<(), String> {
+    policy::check_import()?;
+
+    let keyring_json = keyring::trusted_keyring_json(&app)
+        .map_err(|e| format!("import_offline_subscription failed: {}", e))?;
+
     leaf_sdk_desktop::import_offline_subscription(path, passphrase, keyring_json, move |state| {
-        subscription_state(window.clone(), state.clone());
+        subscription_state(window.app_handle(), state.clone());
     });
+
+    Ok(())
+}
+
+#[tauri::command]
+fn get_drop_folder<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<drop_folder::DropFolderSettings, String> {
+    drop_folder::get_settings(&app).map_err(|e| format!("get_drop_folder failed: {}", e))
+}
+
+#[tauri::command]
+fn set_drop_folder<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    enabled: bool,
+    path: Option<String>,
+) -> Result<drop_folder::DropFolderSettings, String> {
+    drop_folder::set_settings(&app, enabled, path)
+        .map_err(|e| format!("set_drop_folder failed: {}", e))
+}
+
+#[tauri::command]
+fn list_keyring<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<Vec<keyring::KeyringEntry>, String> {
+    keyring::list(&app).map_err(|e| format!("list_keyring failed: {}", e))
+}
+
+#[tauri::command]
+fn add_keyring_key<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    path: String,
+    id: Option<String>,
+) -> Result<Vec<keyring::KeyringEntry>, String> {
+    keyring::add_from_file(&app, path, id).map_err(|e| format!("add_keyring_key failed: {}", e))
+}
+
+#[tauri::command]
+fn revoke_keyring_key<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    id: String,
+) -> Result<(), String> {
+    keyring::revoke(&app, id).map_err(|e| format!("revoke_keyring_key failed: {}", e))
+}
+
+#[tauri::command]
+fn set_default_keyring_keys<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    ids: Vec<String>,
+) -> Result<(), String> {
+    keyring::set_default(&app, ids).map_err(|e| format!("set_default_keyring_keys failed: {}", e))
 }
 
 #[tauri::command]
@@ -217,28 +670,254 @@ fn ping<R: Runtime>(_app: AppHandle<R>, _window: Window<R>) -> Result<String, St
 }
 
 #[tauri::command]
-fn get_pending_leafsub_paths<R: Runtime>(_app: AppHandle<R>, _window: Window<R>) -> Vec<String> {
-    let mut pending = PENDING_LEAFSUB_PATHS.lock();
-    let result = pending.clone();
-    pending.clear();
-    result
+fn take_pending_intents<R: Runtime>(app: AppHandle<R>, _window: Window<R>) -> Vec<intents::Intent> {
+    intents::take_pending(&app)
 }
 
 #[tauri::command]
 fn get_preferences<R: Runtime>(
     _app: AppHandle<R>,
     _window: Window<R>,
-) -> Result<leaf_sdk_desktop::LeafPreferences, String> {
-    leaf_sdk_desktop::get_preferences().map_err(|e| format!("get_preferences failed: {}", e))
+) -> Result<serde_json::Value, String> {
+    helper::get_preferences().map_err(|e| format!("get_preferences failed: {}", e))
 }
 
 #[tauri::command]
 fn set_preferences<R: Runtime>(
+    _app: AppHandle<R>,
+    window: Window<R>,
+    preferences: String,
+) -> Result<helper::PreferencesUpdate, helper::PreferencesError> {
+    let update = helper::set_preferences(preferences)?;
+
+    apply_preference_effects(window, update).map_err(helper::PreferencesError::Failed)
+}
+
+#[tauri::command]
+fn patch_preferences<R: Runtime>(
+    _app: AppHandle<R>,
+    window: Window<R>,
+    patch: String,
+) -> Result<helper::PatchedPreferences, String> {
+    let (preferences, applied) =
+        helper::patch_preferences(patch).map_err(|e| format!("patch_preferences failed: {}", e))?;
+
+    let update =
+        apply_preference_effects(window, helper::PreferencesUpdate::from_applied(applied))?;
+
+    Ok(helper::PatchedPreferences {
+        preferences,
+        update,
+    })
+}
+
+#[tauri::command]
+fn validate_preferences<R: Runtime>(
     _app: AppHandle<R>,
     _window: Window<R>,
     preferences: String,
+) -> helper::ValidationReport {
+    helper::validate_preferences(preferences)
+}
+
+#[tauri::command]
+fn list_preference_history<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<Vec<history::PreferenceSnapshot>, String> {
+    history::list(&app).map_err(|e| format!("list_preference_history failed: {}", e))
+}
+
+#[tauri::command]
+fn diff_preference_snapshots<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<helper::PreferenceChange>, String> {
+    history::diff(&app, from, to).map_err(|e| format!("diff_preference_snapshots failed: {}", e))
+}
+
+#[tauri::command]
+fn revert_preferences<R: Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    id: u64,
+) -> Result<helper::PreferencesUpdate, String> {
+    let applied =
+        history::revert(&app, id).map_err(|e| format!("revert_preferences failed: {}", e))?;
+
+    apply_preference_effects(window, helper::PreferencesUpdate::from_applied(applied))
+}
+
+#[tauri::command]
+fn export_preferences<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    path: String,
+    format: Option<String>,
+) -> Result<(), String> {
+    preferences_file::export_preferences(&app, path, format)
+        .map_err(|e| format!("export_preferences failed: {}", e))
+}
+
+#[tauri::command]
+fn preview_preferences_import<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    path: String,
+) -> Result<preferences_file::ImportPreview, String> {
+    preferences_file::preview_import(&app, path)
+        .map_err(|e| format!("preview_preferences_import failed: {}", e))
+}
+
+#[tauri::command]
+fn import_preferences<R: Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    path: String,
+) -> Result<helper::PreferencesUpdate, String> {
+    let imported = preferences_file::import_preferences(&app, path)
+        .map_err(|e| format!("import_preferences failed: {}", e))?;
+
+    // Write the new rules and DNS settings before anything reloads, so leaf
+    // picks everything up in a single reload.
+    let config_changed = imported.routing_rules_changed || imported.dns_changed;
+    if config_changed {
+        leaf_config::apply(&app).map_err(|e| format!("import_preferences failed: {}", e))?;
+    }
+
+    let mut update = apply_preference_effects(
+        window.clone(),
+        helper::PreferencesUpdate::from_applied(imported.applied),
+    )?;
+    if config_changed && !update.reloaded {
+        update.reloaded = reload_leaf_if_running(window)?;
+    }
+    Ok(update)
+}
+
+#[tauri::command]
+fn list_profiles<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<profiles::ProfileRegistry, String> {
+    profiles::list_profiles(&app).map_err(|e| format!("list_profiles failed: {}", e))
+}
+
+#[tauri::command]
+fn create_profile<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    name: String,
+    preferences: Option<String>,
+) -> Result<profiles::PreferenceProfile, String> {
+    profiles::create_profile(&app, name, preferences)
+        .map_err(|e| format!("create_profile failed: {}", e))
+}
+
+#[tauri::command]
+fn rename_profile<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    name: String,
+    new_name: String,
+) -> Result<(), String> {
+    profiles::rename_profile(&app, name, new_name)
+        .map_err(|e| format!("rename_profile failed: {}", e))
+}
+
+#[tauri::command]
+fn delete_profile<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+    name: String,
 ) -> Result<(), String> {
-    helper::set_preferences(preferences).map_err(|e| format!("set_preferences failed: {}", e))
+    profiles::delete_profile(&app, name).map_err(|e| format!("delete_profile failed: {}", e))
+}
+
+#[tauri::command]
+fn activate_profile<R: Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    name: String,
+) -> Result<helper::PreferencesUpdate, String> {
+    let applied = profiles::activate_profile(&app, name)
+        .map_err(|e| format!("activate_profile failed: {}", e))?;
+
+    apply_preference_effects(window, helper::PreferencesUpdate::from_applied(applied))
+}
+
+#[tauri::command]
+async fn get_geo_catalog<R: Runtime>(
+    _app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<geodata::GeoCatalog, String> {
+    tauri::async_runtime::spawn_blocking(geodata::load_catalog)
+        .await
+        .map_err(|e| format!("get_geo_catalog failed: {}", e))?
+        .map_err(|e| format!("get_geo_catalog failed: {}", e))
+}
+
+#[tauri::command]
+fn get_routing_rules<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<Vec<routing::RoutingRule>, String> {
+    routing::get_rules(&app).map_err(|e| format!("get_routing_rules failed: {}", e))
+}
+
+#[tauri::command]
+fn set_routing_rules<R: Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    rules: String,
+) -> Result<helper::ValidationReport, String> {
+    let report =
+        routing::set_rules(&app, rules).map_err(|e| format!("set_routing_rules failed: {}", e))?;
+    apply_leaf_config(window)?;
+    Ok(report)
+}
+
+#[tauri::command]
+fn get_leaf_routing_rules<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<Vec<serde_json::Value>, String> {
+    routing::leaf_rules(&app).map_err(|e| format!("get_leaf_routing_rules failed: {}", e))
+}
+
+#[tauri::command]
+fn get_dns_settings<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<dns::DnsSettings, String> {
+    dns::get_settings(&app).map_err(|e| format!("get_dns_settings failed: {}", e))
+}
+
+#[tauri::command]
+fn set_dns_settings<R: Runtime>(
+    app: AppHandle<R>,
+    window: Window<R>,
+    settings: String,
+) -> Result<helper::ValidationReport, String> {
+    let report =
+        dns::set_settings(&app, settings).map_err(|e| format!("set_dns_settings failed: {}", e))?;
+    apply_leaf_config(window)?;
+    Ok(report)
+}
+
+#[tauri::command]
+fn get_leaf_dns_config<R: Runtime>(
+    app: AppHandle<R>,
+    _window: Window<R>,
+) -> Result<serde_json::Value, String> {
+    dns::leaf_dns(&app).map_err(|e| format!("get_leaf_dns_config failed: {}", e))
+}
+
+#[tauri::command]
+fn get_policy<R: Runtime>(_app: AppHandle<R>, _window: Window<R>) -> policy::PolicyStatus {
+    policy::status()
 }
 
 #[tauri::command]
@@ -354,12 +1033,83 @@ fn watch_sidecar_binary<R: Runtime>(app: AppHandle<R>, window: Window<R>) -> Res
 }
 
 fn main() {
+    let handler: fn(tauri::ipc::Invoke<tauri::Wry>) -> bool = tauri::generate_handler![
+        start_core,
+        force_shutdown_core,
+        shutdown_core,
+        is_core_running,
+        test_config,
+        is_leaf_running,
+        run_leaf,
+        stop_leaf,
+        reload_leaf,
+        auto_update_subscription,
+        update_subscription,
+        import_qr_code,
+        get_share_qr_code,
+        get_subscription_fetch_options,
+        set_subscription_fetch_options,
+        get_subscription_schedule,
+        set_subscription_schedule,
+        get_subscription_status,
+        get_quota_notifications,
+        set_quota_notifications,
+        list_subscriptions,
+        add_subscription,
+        remove_subscription,
+        switch_subscription,
+        list_subscription_diffs,
+        list_config_versions,
+        roll_back_config,
+        import_offline_subscription,
+        get_preferences,
+        set_preferences,
+        patch_preferences,
+        validate_preferences,
+        list_preference_history,
+        diff_preference_snapshots,
+        revert_preferences,
+        export_preferences,
+        preview_preferences_import,
+        import_preferences,
+        list_profiles,
+        create_profile,
+        rename_profile,
+        delete_profile,
+        activate_profile,
+        get_drop_folder,
+        set_drop_folder,
+        list_keyring,
+        add_keyring_key,
+        revoke_keyring_key,
+        set_default_keyring_keys,
+        verify_file_integrity,
+        ping,
+        get_geo_catalog,
+        get_routing_rules,
+        set_routing_rules,
+        get_leaf_routing_rules,
+        get_policy,
+        get_dns_settings,
+        set_dns_settings,
+        get_leaf_dns_config,
+        detect_linux_system_info,
+        show_main_window,
+        toggle_main_window,
+        get_main_window_state,
+        get_versions,
+        start_file_watcher,
+        stop_file_watcher,
+        is_file_watcher_running,
+        watch_sidecar_binary,
+        take_pending_intents,
+    ];
+
     tauri::Builder::default()
         .plugin(tauri_plugin_single_instance::init(|app, argv, _cwd| {
             info!("Single instance triggered with args: {:?}", argv);
 
-            let paths: Vec<String> = argv.into_iter().skip(1).collect();
-            emit_leafsub_paths(&app, &paths);
+            intents::push(app, intents::from_args(argv));
         }))
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_process::init())
@@ -397,6 +1147,13 @@ fn main() {
                 .rotation_strategy(tauri_plugin_log::RotationStrategy::KeepSome(5))
                 .build(),
         )
+        .on_page_load(|webview, payload| {
+            // A reloaded page has lost its listeners until it takes the
+            // pending intents again.
+            if webview.label() == "main" && payload.event() == PageLoadEvent::Started {
+                intents::page_loading();
+            }
+        })
         .setup(move |app| {
             let handle = app.handle();
             tray::create_tray(&handle.clone())?;
@@ -407,6 +1164,21 @@ fn main() {
             // update assets
             let version = app.package_info().version.clone();
             leaf_sdk_desktop::update_assets(version.major, version.minor, version.patch)?;
+            geodata::init_asset_dir(&leaf_sidecar_program(app.app_handle()));
+            geodata::invalidate_catalog();
+            geodata::warm_catalog();
+
+            // enforce managed policy
+            match helper::enforce_policy() {
+                Ok(changes) if !changes.is_empty() => {
+                    info!("Policy changed {} preference(s)", changes.len());
+                }
+                Ok(_) => {}
+                Err(e) => log::error!("Failed to enforce policy: {}", e),
+            }
+            if let Err(e) = install_pinned_subscription(&handle) {
+                log::error!("Failed to install the pinned subscription: {}", e);
+            }
 
             #[cfg(any(target_os = "linux", all(debug_assertions, windows)))]
             {
@@ -414,9 +1186,29 @@ fn main() {
                 app.deep_link().register_all()?;
             }
 
-            let initial_paths: Vec<String> = std::env::args().skip(1).collect();
-            if !initial_paths.is_empty() {
-                emit_leafsub_paths(&handle, &initial_paths);
+            // Elsewhere deep links arrive as arguments, which keep the raw link
+            // that signatures are checked against; the plugin only has it parsed.
+            #[cfg(target_os = "macos")]
+            {
+                use tauri_plugin_deep_link::DeepLinkExt;
+                let deep_link_handle = handle.clone();
+                app.deep_link().on_open_url(move |event| {
+                    let urls = event.urls().iter().map(|url| url.to_string());
+                    intents::push(&deep_link_handle, intents::from_urls(urls));
+                });
+
+                if let Ok(Some(urls)) = app.deep_link().get_current() {
+                    let urls = urls.iter().map(|url| url.to_string());
+                    intents::push(&handle, intents::from_urls(urls));
+                }
+            }
+
+            intents::push(&handle, intents::from_args(std::env::args()));
+
+            scheduler::start(handle.clone());
+            quota::start(handle.clone());
+            if let Err(e) = drop_folder::start(handle.clone()) {
+                log::error!("Failed to start drop folder watcher: {}", e);
             }
 
             // Write wintun.dll before starting core
@@ -441,34 +1233,13 @@ fn main() {
                 api.prevent_close();
             }
         })
-        .invoke_handler(tauri::generate_handler![
-            start_core,
-            force_shutdown_core,
-            shutdown_core,
-            is_core_running,
-            test_config,
-            is_leaf_running,
-            run_leaf,
-            stop_leaf,
-            reload_leaf,
-            auto_update_subscription,
-            update_subscription,
-            import_offline_subscription,
-            get_preferences,
-            set_preferences,
-            verify_file_integrity,
-            ping,
-            detect_linux_system_info,
-            show_main_window,
-            toggle_main_window,
-            get_main_window_state,
-            get_versions,
-            start_file_watcher,
-            stop_file_watcher,
-            is_file_watcher_running,
-            watch_sidecar_binary,
-            get_pending_leafsub_paths,
-        ])
+        .invoke_handler(move |invoke| {
+            if let Err(e) = policy::check_command(invoke.message.command()) {
+                invoke.resolver.reject(e);
+                return true;
+            }
+            handler(invoke)
+        })
         .build(tauri::generate_context!())
         .expect("error while building tauri application")
         .run(|_app_handle, event| {
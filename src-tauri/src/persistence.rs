@@ -0,0 +1,29 @@
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tauri::{AppHandle, Runtime};
+use tauri_plugin_store::StoreExt;
+
+pub fn load<R: Runtime, T: DeserializeOwned + Default>(
+    app: &AppHandle<R>,
+    file: &str,
+    key: &str,
+) -> Result<T> {
+    let store = app.store(file)?;
+    match store.get(key) {
+        Some(value) => Ok(serde_json::from_value(value)?),
+        None => Ok(T::default()),
+    }
+}
+
+pub fn save<R: Runtime, T: Serialize>(
+    app: &AppHandle<R>,
+    file: &str,
+    key: &str,
+    value: &T,
+) -> Result<()> {
+    let store = app.store(file)?;
+    store.set(key, serde_json::to_value(value)?);
+    store.save()?;
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use log::{error, info};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::PathBuf;
+
+/// Which subscription updates are allowed: `manual` only permits the ones a
+/// user starts, `disabled` permits none.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateMode {
+    #[default]
+    Automatic,
+    Manual,
+    Disabled,
+}
+
+/// Read-only policy installed by an administrator. Every preference listed in
+/// `preferences` is forced to the given value and cannot be changed by users.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Policy {
+    #[serde(default)]
+    pub preferences: Map<String, Value>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub disabled_commands: Vec<String>,
+    #[serde(default)]
+    pub update_mode: UpdateMode,
+    /// Whether the app looks for new versions of itself.
+    #[serde(default = "default_app_updates")]
+    pub app_updates: bool,
+}
+
+fn default_app_updates() -> bool {
+    true
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            preferences: Map::new(),
+            client_id: None,
+            disabled_commands: Vec::new(),
+            update_mode: UpdateMode::default(),
+            app_updates: default_app_updates(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct PolicyStatus {
+    pub path: String,
+    pub loaded: bool,
+    pub error: Option<String>,
+    pub policy: Policy,
+}
+
+static POLICY: Lazy<PolicyStatus> = Lazy::new(load);
+
+fn policy_path() -> PathBuf {
+    #[cfg(target_os = "windows")]
+    {
+        let program_data =
+            std::env::var_os("ProgramData").unwrap_or_else(|| "C:\\ProgramData".into());
+        PathBuf::from(program_data)
+            .join("leaf-desktop")
+            .join("policy.json")
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        PathBuf::from("/Library/Application Support/leaf-desktop/policy.json")
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        PathBuf::from("/etc/leaf-desktop/policy.json")
+    }
+}
+
+fn load() -> PolicyStatus {
+    let path = policy_path();
+    let mut status = PolicyStatus {
+        path: path.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+
+    if !path.exists() {
+        return status;
+    }
+
+    let policy = fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| serde_json::from_str::<Policy>(&contents).map_err(|e| e.to_string()));
+
+    match policy {
+        Ok(policy) => {
+            info!("Loaded policy from {}", status.path);
+            status.loaded = true;
+            status.policy = policy;
+        }
+        Err(e) => {
+            error!("Failed to load policy from {}: {}", status.path, e);
+            status.error = Some(e);
+        }
+    }
+
+    status
+}
+
+pub fn status() -> PolicyStatus {
+    POLICY.clone()
+}
+
+pub fn policy() -> &'static Policy {
+    &POLICY.policy
+}
+
+pub fn forced_preferences() -> &'static Map<String, Value> {
+    &policy().preferences
+}
+
+pub fn locked_fields() -> Vec<String> {
+    forced_preferences().keys().cloned().collect()
+}
+
+/// Fails while a policy file exists but could not be read. What it locks is
+/// unknown then, so the changes it could lock are refused rather than allowed.
+pub fn check_loaded() -> Result<(), String> {
+    check_status(&POLICY)
+}
+
+fn check_status(status: &PolicyStatus) -> Result<(), String> {
+    match &status.error {
+        Some(e) => Err(format!(
+            "the policy at {} could not be read: {}",
+            status.path, e
+        )),
+        None => Ok(()),
+    }
+}
+
+pub fn is_command_disabled(command: &str) -> bool {
+    policy()
+        .disabled_commands
+        .iter()
+        .any(|disabled| disabled == command)
+}
+
+/// Fails when the policy disables `command`. Backend paths that do what a
+/// command does, such as deep links, check the name of that command.
+pub fn check_command(command: &str) -> Result<(), String> {
+    if is_command_disabled(command) {
+        return Err(format!("{} is disabled by policy", command));
+    }
+    Ok(())
+}
+
+pub fn is_subscription_pinned() -> bool {
+    policy().client_id.is_some()
+}
+
+pub fn pinned_client_id() -> Option<&'static str> {
+    policy().client_id.as_deref()
+}
+
+fn check_update_mode(mode: UpdateMode, automatic: bool) -> Result<(), String> {
+    match mode {
+        UpdateMode::Disabled => Err("subscription updates are disabled by policy".to_string()),
+        UpdateMode::Manual if automatic => {
+            Err("automatic subscription updates are disabled by policy".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Fails when the policy does not allow a subscription update. `automatic`
+/// marks updates that no user asked for, such as scheduled refreshes.
+pub fn check_update(automatic: bool) -> Result<(), String> {
+    check_loaded()?;
+    check_update_mode(policy().update_mode, automatic)
+}
+
+/// Fails when the policy pins a subscription other than `client_id`.
+pub fn check_client_id(client_id: &str) -> Result<(), String> {
+    check_loaded()?;
+    match &policy().client_id {
+        Some(pinned) if pinned != client_id => {
+            Err("the subscription is pinned by policy".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Fails when offline subscriptions may not be imported, as they would
+/// replace a subscription pinned by policy.
+pub fn check_import() -> Result<(), String> {
+    check_loaded()?;
+    if is_subscription_pinned() {
+        return Err("the subscription is pinned by policy".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_mode_limits_updates() {
+        assert!(check_update_mode(UpdateMode::Automatic, true).is_ok());
+        assert!(check_update_mode(UpdateMode::Manual, false).is_ok());
+        assert!(check_update_mode(UpdateMode::Manual, true).is_err());
+        assert!(check_update_mode(UpdateMode::Disabled, false).is_err());
+    }
+
+    #[test]
+    fn parses_policy_files() {
+        let policy: Policy = serde_json::from_str(
+            r#"{ "preferences": { "bypass_lan": true }, "update_mode": "manual" }"#,
+        )
+        .unwrap();
+        assert_eq!(policy.update_mode, UpdateMode::Manual);
+        assert!(policy.client_id.is_none());
+        assert!(policy.app_updates);
+        assert!(serde_json::from_str::<Policy>(r#"{ "update_mode": "never" }"#).is_err());
+    }
+
+    #[test]
+    fn unreadable_policy_fails_closed() {
+        let mut status = PolicyStatus::default();
+        assert!(check_status(&status).is_ok());
+        status.error = Some("expected value at line 1 column 1".to_string());
+        assert!(check_status(&status).is_err());
+    }
+}
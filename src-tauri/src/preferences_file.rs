@@ -0,0 +1,357 @@
+use crate::dns::{self, DnsSettings};
+use crate::helper::{
+    self, AppliedPreferences, FieldIssue, PreferenceChange, UpdateLeafPreferences, ValidationReport,
+};
+use crate::routing::{self, RoutingRule};
+use crate::util;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+use tauri::{AppHandle, Runtime};
+
+/// Version of the exported file layout. Bump it together with a new entry in
+/// `MIGRATIONS` whenever a field is added or renamed.
+pub const SCHEMA_VERSION: u32 = 2;
+const MIN_SCHEMA_VERSION: u32 = 1;
+
+type Migration = fn(&mut Map<String, Value>);
+
+// `MIGRATIONS[n]` upgrades a file of version `MIN_SCHEMA_VERSION + n` to the
+// next version.
+const MIGRATIONS: &[Migration] = &[migrate_v1_to_v2];
+
+// Version 2 added the routing rules and DNS settings, which version 1 files
+// leave untouched on import. Version 1 files may also lack preferences that
+// were added while it was current.
+fn migrate_v1_to_v2(document: &mut Map<String, Value>) {
+    let Some(Value::Object(preferences)) = document.get_mut("preferences") else {
+        return;
+    };
+    let bypass_lan = preferences
+        .get("bypass_lan")
+        .and_then(Value::as_bool)
+        .unwrap_or(true);
+
+    let defaults = [
+        ("bypass_lan_in_core", Value::Bool(bypass_lan)),
+        ("fake_ip", Value::Bool(false)),
+        ("force_resolve_domain", Value::Bool(false)),
+        ("internal_dns_server", Value::Bool(false)),
+    ];
+    for (field, value) in defaults {
+        preferences.entry(field).or_insert(value);
+    }
+}
+
+// Brings `document` from `version` up to `SCHEMA_VERSION`.
+fn migrate(document: &mut Map<String, Value>, version: u32) {
+    let first = (version - MIN_SCHEMA_VERSION) as usize;
+    for migration in &MIGRATIONS[first..] {
+        migration(document);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PreferencesFile {
+    pub schema_version: u32,
+    pub exported_at: u64,
+    pub app_version: String,
+    pub preferences: UpdateLeafPreferences,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing_rules: Option<Vec<RoutingRule>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsSettings>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImportPreview {
+    pub schema_version: u32,
+    pub report: ValidationReport,
+    pub changes: Vec<PreferenceChange>,
+    pub routing_rules_changed: bool,
+    pub dns_changed: bool,
+}
+
+pub struct ImportResult {
+    pub applied: AppliedPreferences,
+    pub routing_rules_changed: bool,
+    pub dns_changed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FileFormat {
+    Json,
+    Toml,
+}
+
+impl FileFormat {
+    fn detect(path: &Path, format: Option<&str>) -> Result<Self> {
+        let format = match format {
+            Some(format) => format.to_lowercase(),
+            None => path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_else(|| "json".to_string()),
+        };
+
+        match format.as_str() {
+            "json" => Ok(FileFormat::Json),
+            "toml" => Ok(FileFormat::Toml),
+            other => Err(anyhow!("unsupported preferences format '{}'", other)),
+        }
+    }
+}
+
+pub fn export_preferences<R: Runtime>(
+    app: &AppHandle<R>,
+    path: String,
+    format: Option<String>,
+) -> Result<()> {
+    let path = Path::new(&path);
+    let file = PreferencesFile {
+        schema_version: SCHEMA_VERSION,
+        exported_at: util::now(),
+        app_version: app.package_info().version.to_string(),
+        preferences: helper::current_preferences()?,
+        routing_rules: Some(routing::get_rules(app)?),
+        dns: Some(dns::get_settings(app)?),
+    };
+
+    let contents = match FileFormat::detect(path, format.as_deref())? {
+        FileFormat::Json => serde_json::to_string_pretty(&file)?,
+        FileFormat::Toml => toml::to_string_pretty(&file)?,
+    };
+
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+struct LoadedFile {
+    schema_version: u32,
+    preferences: Option<UpdateLeafPreferences>,
+    routing_rules: Option<Vec<RoutingRule>>,
+    dns: Option<DnsSettings>,
+    report: ValidationReport,
+}
+
+fn merge_report(report: &mut ValidationReport, prefix: &str, other: ValidationReport) {
+    let prefixed = |mut issue: FieldIssue| {
+        issue.field = format!("{}.{}", prefix, issue.field);
+        issue
+    };
+    report.errors.extend(other.errors.into_iter().map(prefixed));
+    report
+        .warnings
+        .extend(other.warnings.into_iter().map(prefixed));
+}
+
+fn parse_document(mut document: Value) -> Result<LoadedFile> {
+    let schema_version = document
+        .get("schema_version")
+        .ok_or_else(|| anyhow!("not a preferences export: schema_version is missing"))?
+        .as_u64()
+        .and_then(|version| u32::try_from(version).ok())
+        .ok_or_else(|| anyhow!("schema_version must be a positive integer"))?;
+
+    if !(MIN_SCHEMA_VERSION..=SCHEMA_VERSION).contains(&schema_version) {
+        return Err(anyhow!(
+            "file uses schema version {}, but this build only understands {} to {}",
+            schema_version,
+            MIN_SCHEMA_VERSION,
+            SCHEMA_VERSION
+        ));
+    }
+    if let Some(object) = document.as_object_mut() {
+        migrate(object, schema_version);
+    }
+
+    let preferences = document
+        .get("preferences")
+        .ok_or_else(|| anyhow!("file has no preferences section"))?;
+    let (preferences, mut report) = helper::parse_preferences(&preferences.to_string());
+
+    let routing_rules: Option<Vec<RoutingRule>> = match document.get("routing_rules") {
+        Some(rules) => Some(serde_json::from_value(rules.clone())?),
+        None => None,
+    };
+    if let Some(rules) = &routing_rules {
+        merge_report(&mut report, "routing_rules", routing::validate_rules(rules));
+    }
+
+    let dns: Option<DnsSettings> = match document.get("dns") {
+        Some(settings) => Some(serde_json::from_value(settings.clone())?),
+        None => None,
+    };
+    if let Some(settings) = &dns {
+        merge_report(&mut report, "dns", dns::validate_settings(settings));
+    }
+
+    Ok(LoadedFile {
+        schema_version,
+        preferences,
+        routing_rules,
+        dns,
+        report,
+    })
+}
+
+fn load_file(path: &str) -> Result<LoadedFile> {
+    let path = Path::new(path);
+    let contents = fs::read_to_string(path)?;
+
+    let document: Value = match FileFormat::detect(path, None)? {
+        FileFormat::Json => serde_json::from_str(&contents)?,
+        FileFormat::Toml => serde_json::to_value(toml::from_str::<toml::Value>(&contents)?)?,
+    };
+
+    parse_document(document)
+}
+
+pub fn preview_import<R: Runtime>(app: &AppHandle<R>, path: String) -> Result<ImportPreview> {
+    let loaded = load_file(&path)?;
+
+    let changes = match &loaded.preferences {
+        Some(preferences) => helper::diff_preferences(&helper::current_preferences()?, preferences),
+        None => Vec::new(),
+    };
+
+    Ok(ImportPreview {
+        schema_version: loaded.schema_version,
+        report: loaded.report,
+        changes,
+        routing_rules_changed: loaded
+            .routing_rules
+            .is_some_and(|rules| rules != routing::get_rules(app).unwrap_or_default()),
+        dns_changed: loaded
+            .dns
+            .is_some_and(|settings| settings != dns::get_settings(app).unwrap_or_default()),
+    })
+}
+
+/// Applies the preferences, routing rules and DNS settings of an export while
+/// holding the preferences lock, so nothing else changes them in between. All
+/// three are checked before any of them is applied.
+pub fn import_preferences<R: Runtime>(app: &AppHandle<R>, path: String) -> Result<ImportResult> {
+    let loaded = load_file(&path)?;
+
+    let preferences = match loaded.preferences {
+        Some(preferences) if loaded.report.is_valid() => preferences,
+        _ => return Err(anyhow!("invalid preferences: {}", loaded.report)),
+    };
+    let report = helper::validate_update_preferences(&preferences);
+    if !report.is_valid() {
+        return Err(anyhow!("invalid preferences: {}", report));
+    }
+
+    let rules = match loaded.routing_rules {
+        Some(rules) if rules != routing::get_rules(app)? => Some(rules),
+        _ => None,
+    };
+    if let Some(rules) = &rules {
+        routing::check_rules(app, rules)?;
+    }
+    let dns = match loaded.dns {
+        Some(settings) if settings != dns::get_settings(app)? => Some(settings),
+        _ => None,
+    };
+    if let Some(settings) = &dns {
+        dns::check_settings(settings)?;
+    }
+
+    let guard = helper::lock_preferences();
+    let result = ImportResult {
+        applied: helper::apply_locked(&guard, preferences)?,
+        routing_rules_changed: rules.is_some(),
+        dns_changed: dns.is_some(),
+    };
+    if let Some(rules) = rules {
+        routing::save_rules(app, &rules)?;
+    }
+    if let Some(settings) = dns {
+        dns::save_settings(app, &settings)?;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn document(schema_version: u32) -> Value {
+        json!({
+            "schema_version": schema_version,
+            "exported_at": 0,
+            "app_version": "1.0.0",
+            "preferences": helper::test_preferences(),
+        })
+    }
+
+    #[test]
+    fn reads_version_1_without_routing_or_dns() {
+        let loaded = parse_document(document(1)).unwrap();
+        assert!(loaded.report.is_valid());
+        assert!(loaded.preferences.is_some());
+        assert!(loaded.routing_rules.is_none());
+        assert!(loaded.dns.is_none());
+    }
+
+    #[test]
+    fn migrates_preferences_missing_from_version_1() {
+        let mut document = document(1);
+        let preferences = document["preferences"].as_object_mut().unwrap();
+        preferences.remove("bypass_lan_in_core");
+        preferences.remove("internal_dns_server");
+        preferences.insert("bypass_lan".to_string(), Value::Bool(false));
+
+        let loaded = parse_document(document).unwrap();
+        assert!(loaded.report.is_valid());
+        let preferences = loaded.preferences.unwrap();
+        assert!(!preferences.bypass_lan_in_core);
+        assert!(!preferences.internal_dns_server);
+    }
+
+    #[test]
+    fn has_a_migration_for_every_older_version() {
+        assert_eq!(
+            MIGRATIONS.len(),
+            (SCHEMA_VERSION - MIN_SCHEMA_VERSION) as usize
+        );
+    }
+
+    #[test]
+    fn reads_routing_and_dns_sections() {
+        let mut document = document(SCHEMA_VERSION);
+        document["routing_rules"] = json!([{
+            "kind": "domain_suffix",
+            "value": "not a domain",
+            "action": { "type": "direct" }
+        }]);
+        document["dns"] = json!({ "upstreams": [], "hosts": [] });
+
+        let loaded = parse_document(document).unwrap();
+        assert_eq!(loaded.routing_rules.unwrap().len(), 1);
+        assert_eq!(loaded.dns, Some(DnsSettings::default()));
+        assert_eq!(loaded.report.errors[0].field, "routing_rules.rules[0]");
+    }
+
+    #[test]
+    fn rejects_files_without_envelope_or_from_newer_builds() {
+        let bare = document(1)["preferences"].clone();
+        assert!(parse_document(bare).is_err());
+        assert!(parse_document(document(SCHEMA_VERSION + 1)).is_err());
+        assert!(parse_document(document(0)).is_err());
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        let file: PreferencesFile = serde_json::from_value(document(SCHEMA_VERSION)).unwrap();
+        let contents = toml::to_string_pretty(&file).unwrap();
+        let document = serde_json::to_value(toml::from_str::<toml::Value>(&contents).unwrap());
+        let loaded = parse_document(document.unwrap()).unwrap();
+        assert_eq!(loaded.preferences, Some(file.preferences));
+    }
+}
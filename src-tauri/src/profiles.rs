@@ -0,0 +1,158 @@
+use crate::helper::{self, AppliedPreferences, UpdateLeafPreferences};
+use crate::persistence;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Runtime};
+
+const PROFILES_FILE: &str = "profiles.json";
+const PROFILES_KEY: &str = "profiles";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PreferenceProfile {
+    pub name: String,
+    pub preferences: UpdateLeafPreferences,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProfileRegistry {
+    pub profiles: Vec<PreferenceProfile>,
+    pub active: Option<String>,
+}
+
+impl ProfileRegistry {
+    fn position(&self, name: &str) -> Option<usize> {
+        self.profiles
+            .iter()
+            .position(|profile| profile.name == name)
+    }
+
+    fn find(&self, name: &str) -> Result<usize> {
+        self.position(name)
+            .ok_or_else(|| anyhow!("profile '{}' does not exist", name))
+    }
+}
+
+fn load<R: Runtime>(app: &AppHandle<R>) -> Result<ProfileRegistry> {
+    persistence::load(app, PROFILES_FILE, PROFILES_KEY)
+}
+
+fn save<R: Runtime>(app: &AppHandle<R>, registry: &ProfileRegistry) -> Result<()> {
+    persistence::save(app, PROFILES_FILE, PROFILES_KEY, registry)
+}
+
+fn check_name(registry: &ProfileRegistry, name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(anyhow!("profile name must not be empty"));
+    }
+    if registry.position(name).is_some() {
+        return Err(anyhow!("profile '{}' already exists", name));
+    }
+    Ok(name.to_string())
+}
+
+pub fn list_profiles<R: Runtime>(app: &AppHandle<R>) -> Result<ProfileRegistry> {
+    load(app)
+}
+
+/// Creates a profile from the given preferences JSON, or from the current
+/// preferences when none are given.
+pub fn create_profile<R: Runtime>(
+    app: &AppHandle<R>,
+    name: String,
+    preferences: Option<String>,
+) -> Result<PreferenceProfile> {
+    let mut registry = load(app)?;
+    let name = check_name(&registry, &name)?;
+
+    let preferences = match preferences {
+        Some(preferences) => {
+            let (preferences, report) = helper::parse_preferences(&preferences);
+            match preferences {
+                Some(preferences) if report.is_valid() => preferences,
+                _ => return Err(anyhow!("invalid preferences: {}", report)),
+            }
+        }
+        None => helper::current_preferences()?,
+    };
+
+    let profile = PreferenceProfile { name, preferences };
+    registry.profiles.push(profile.clone());
+    save(app, &registry)?;
+
+    Ok(profile)
+}
+
+pub fn rename_profile<R: Runtime>(
+    app: &AppHandle<R>,
+    name: String,
+    new_name: String,
+) -> Result<()> {
+    let mut registry = load(app)?;
+    let index = registry.find(&name)?;
+    let new_name = check_name(&registry, &new_name)?;
+
+    if registry.active.as_deref() == Some(name.as_str()) {
+        registry.active = Some(new_name.clone());
+    }
+    registry.profiles[index].name = new_name;
+
+    save(app, &registry)
+}
+
+pub fn delete_profile<R: Runtime>(app: &AppHandle<R>, name: String) -> Result<()> {
+    let mut registry = load(app)?;
+    let index = registry.find(&name)?;
+
+    registry.profiles.remove(index);
+    if registry.active.as_deref() == Some(name.as_str()) {
+        registry.active = None;
+    }
+
+    save(app, &registry)
+}
+
+/// Applies the profile's preferences and marks it as active. The caller is
+/// responsible for reloading leaf afterwards.
+pub fn activate_profile<R: Runtime>(
+    app: &AppHandle<R>,
+    name: String,
+) -> Result<AppliedPreferences> {
+    let mut registry = load(app)?;
+    let index = registry.find(&name)?;
+
+    let applied = helper::apply_update_preferences(registry.profiles[index].preferences.clone())?;
+
+    registry.active = Some(name);
+    save(app, &registry)?;
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> ProfileRegistry {
+        ProfileRegistry {
+            profiles: vec![PreferenceProfile {
+                name: "Office".to_string(),
+                preferences: helper::test_preferences(),
+            }],
+            active: None,
+        }
+    }
+
+    #[test]
+    fn trims_new_names() {
+        assert_eq!(check_name(&registry(), "  Home ").unwrap(), "Home");
+    }
+
+    #[test]
+    fn rejects_empty_and_taken_names() {
+        assert!(check_name(&registry(), "  ").is_err());
+        assert!(check_name(&registry(), " Office").is_err());
+        assert!(registry().find("Home").is_err());
+        assert_eq!(registry().find("Office").unwrap(), 0);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::deeplink::{self, DeepLinkAction};
+use crate::{persistence, subscriptions, util};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ed25519_dalek::SigningKey;
+use image::{DynamicImage, GrayImage, ImageFormat, Luma, RgbaImage};
+use qrcode::QrCode;
+use serde::Serialize;
+use std::io::Cursor;
+use tauri::{AppHandle, Runtime};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+
+const DEEP_LINK_PREFIX: &str = "leafvpn://";
+
+const QR_CODE_SIZE: u32 = 512;
+const SHARE_LINK_TTL_SECS: u64 = 24 * 60 * 60;
+const SHARE_WARNING: &str = "This QR code contains your client id and expires in 24 hours. \
+Anyone who scans it before then can use your subscription and its traffic, so only show it to devices you own.";
+const NEVER_EXPIRES_WARNING: &str = "This QR code contains your client id and does not expire. \
+Anyone who scans it can use your subscription and its traffic, so only show it to devices you own.";
+
+const SHARE_KEY_FILE: &str = "share_key.json";
+const SHARE_KEY_KEY: &str = "secret_key";
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ShareQrCode {
+    pub link: String,
+    /// Base64 encoded PNG image.
+    pub png: String,
+    pub warning: String,
+    /// Unix seconds after which the link is refused, `None` if it never expires.
+    pub expires_at: Option<u64>,
+    /// Public key that signed the link. Devices that trust it in their
+    /// keyring know the expiry was not stripped or extended.
+    pub public_key: Option<String>,
+}
+
+fn decode(image: &GrayImage) -> Vec<String> {
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+        image.width() as usize,
+        image.height() as usize,
+        |x, y| image.get_pixel(x as u32, y as u32).0[0],
+    );
+
+    prepared
+        .detect_grids()
+        .into_iter()
+        .filter_map(|grid| grid.decode().ok())
+        .map(|(_, content)| content.trim().to_string())
+        .collect()
+}
+
+fn read_file(path: &str) -> Result<GrayImage> {
+    Ok(image::open(path)?.to_luma8())
+}
+
+// Must not run on the main thread, where reading the clipboard can deadlock on Linux.
+fn read_clipboard<R: Runtime>(app: &AppHandle<R>) -> Result<GrayImage> {
+    let image = app
+        .clipboard()
+        .read_image()
+        .map_err(|e| anyhow!("no image on the clipboard: {}", e))?;
+    let rgba = RgbaImage::from_raw(image.width(), image.height(), image.rgba().to_vec())
+        .ok_or_else(|| anyhow!("clipboard image has an unexpected size"))?;
+    Ok(DynamicImage::ImageRgba8(rgba).to_luma8())
+}
+
+/// Turns the text of a QR code into the client id it installs.
+fn to_profile(content: &str) -> Option<String> {
+    if content.to_lowercase().starts_with(DEEP_LINK_PREFIX) {
+        return match deeplink::parse(content) {
+            Ok(DeepLinkAction::Install { profile }) => Some(profile),
+            _ => None,
+        };
+    }
+    util::is_client_id(content).then(|| content.to_string())
+}
+
+/// Installs the subscription from a QR code in the image at `path`, or in the
+/// clipboard when no path is given, and returns its client id. The image is
+/// read and decoded on a blocking thread.
+pub async fn import<R: Runtime>(app: &AppHandle<R>, path: Option<String>) -> Result<String> {
+    let handle = app.clone();
+    let contents = tauri::async_runtime::spawn_blocking(move || -> Result<Vec<String>> {
+        let image = match path {
+            Some(path) => read_file(&path)?,
+            None => read_clipboard(&handle)?,
+        };
+        Ok(decode(&image))
+    })
+    .await??;
+
+    if contents.is_empty() {
+        return Err(anyhow!("no QR code found in the image"));
+    }
+    let profile = contents
+        .iter()
+        .find_map(|content| to_profile(content))
+        .ok_or_else(|| anyhow!("the QR code does not contain a subscription"))?;
+
+    deeplink::run(
+        app,
+        DeepLinkAction::Install {
+            profile: profile.clone(),
+        },
+    )?;
+    Ok(profile)
+}
+
+// Builds the unsigned `leafvpn://install` link for `client_id`.
+fn install_link(client_id: &str) -> String {
+    format!(
+        "{}install?profile={}",
+        DEEP_LINK_PREFIX,
+        URL_SAFE_NO_PAD.encode(client_id)
+    )
+}
+
+fn render_png(content: &str) -> Result<Vec<u8>> {
+    let image = QrCode::new(content)?
+        .render::<Luma<u8>>()
+        .min_dimensions(QR_CODE_SIZE, QR_CODE_SIZE)
+        .build();
+
+    let mut png = Vec::new();
+    DynamicImage::ImageLuma8(image).write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+// The key this device signs share links with, created on first use.
+fn share_key<R: Runtime>(app: &AppHandle<R>) -> Result<SigningKey> {
+    let stored: Option<String> = persistence::load(app, SHARE_KEY_FILE, SHARE_KEY_KEY)?;
+    let secret = match stored {
+        Some(stored) => <[u8; 32]>::try_from(STANDARD.decode(stored)?.as_slice())
+            .map_err(|_| anyhow!("the stored share key is invalid"))?,
+        None => {
+            let mut secret = [0; 32];
+            getrandom::getrandom(&mut secret)
+                .map_err(|e| anyhow!("failed to create a share key: {}", e))?;
+            persistence::save(
+                app,
+                SHARE_KEY_FILE,
+                SHARE_KEY_KEY,
+                &Some(STANDARD.encode(secret)),
+            )?;
+            secret
+        }
+    };
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+// Signs the install link of `client_id` together with an expiry
+// `SHARE_LINK_TTL_SECS` after `now`.
+fn expiring_link(client_id: &str, key: &SigningKey, now: u64) -> (String, u64) {
+    let expires_at = now + SHARE_LINK_TTL_SECS;
+    (
+        deeplink::sign(&install_link(client_id), expires_at, key),
+        expires_at,
+    )
+}
+
+/// Renders the install link of the current subscription as a QR code for the
+/// mobile app to scan. The link expires after a day and is signed by this
+/// device, unless `never_expires` asks for a plain link.
+pub fn share<R: Runtime>(app: &AppHandle<R>, never_expires: bool) -> Result<ShareQrCode> {
+    let client_id = subscriptions::current_client_id()?
+        .ok_or_else(|| anyhow!("no subscription is configured"))?;
+
+    let (link, expires_at, public_key, warning) = if never_expires {
+        (install_link(&client_id), None, None, NEVER_EXPIRES_WARNING)
+    } else {
+        let key = share_key(app)?;
+        let (link, expires_at) = expiring_link(&client_id, &key, util::now());
+        let public_key = URL_SAFE_NO_PAD.encode(key.verifying_key().as_bytes());
+        (link, Some(expires_at), Some(public_key), SHARE_WARNING)
+    };
+    let png = render_png(&link)?;
+
+    Ok(ShareQrCode {
+        link,
+        png: STANDARD.encode(png),
+        warning: warning.to_string(),
+        expires_at,
+        public_key,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT_ID: &str = "123e4567-e89b-12d3-a456-426614174000";
+
+    #[test]
+    fn reads_client_ids() {
+        assert_eq!(to_profile(CLIENT_ID).as_deref(), Some(CLIENT_ID));
+    }
+
+    #[test]
+    fn shares_links_the_parser_accepts() {
+        let link = install_link(CLIENT_ID);
+        assert!(!link.contains("exp="));
+        assert_eq!(
+            deeplink::parse(&link).unwrap(),
+            DeepLinkAction::Install {
+                profile: CLIENT_ID.to_string()
+            }
+        );
+        assert_eq!(to_profile(&link).as_deref(), Some(CLIENT_ID));
+    }
+
+    #[test]
+    fn shares_expiring_links_by_default() {
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let now = util::now();
+        let (link, expires_at) = expiring_link(CLIENT_ID, &key, now);
+        assert_eq!(expires_at, now + SHARE_LINK_TTL_SECS);
+        assert!(link.contains(&format!("exp={}", expires_at)));
+        assert_eq!(to_profile(&link).as_deref(), Some(CLIENT_ID));
+
+        let (expired, _) = expiring_link(CLIENT_ID, &key, now - 2 * SHARE_LINK_TTL_SECS);
+        assert_eq!(to_profile(&expired), None);
+    }
+
+    #[test]
+    fn renders_a_png() {
+        let png = render_png(&install_link(CLIENT_ID)).unwrap();
+        let image = image::load_from_memory(&png).unwrap();
+        assert!(image.width() >= QR_CODE_SIZE);
+    }
+
+    #[test]
+    fn ignores_other_contents() {
+        assert_eq!(to_profile("leafvpn://connect"), None);
+        assert_eq!(to_profile("https://example.com"), None);
+        assert_eq!(to_profile("deadbeef"), None);
+        assert_eq!(to_profile(""), None);
+    }
+}
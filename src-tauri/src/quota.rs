@@ -0,0 +1,283 @@
+use crate::{persistence, util};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use log::error;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Runtime};
+
+const QUOTA_FILE: &str = "quota.json";
+const QUOTA_KEY: &str = "quota";
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(600);
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+// Both the periodic thread and subscription updates run checks, which must not
+// both notify about the same threshold.
+static CHECK_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SubscriptionStatus {
+    /// Total traffic in bytes, `0` meaning unlimited.
+    pub traffic: u64,
+    pub used_traffic: u64,
+    pub remaining_bytes: Option<u64>,
+    pub percent_used: Option<f64>,
+    pub expire_time: Option<String>,
+    pub days_until_expiry: Option<i64>,
+    pub expired: bool,
+    pub traffic_exhausted: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QuotaSettings {
+    pub enabled: bool,
+    pub traffic_thresholds: Vec<u8>,
+    pub expiry_days: Vec<u32>,
+    #[serde(default)]
+    pub notified: Vec<String>,
+}
+
+impl Default for QuotaSettings {
+    fn default() -> Self {
+        QuotaSettings {
+            enabled: true,
+            traffic_thresholds: vec![80, 95],
+            expiry_days: vec![7, 1],
+            notified: Vec::new(),
+        }
+    }
+}
+
+fn parse_expire_time(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S") {
+        return Some(time.and_utc());
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+}
+
+// Whole days left, counting a started day as a full one.
+fn days_until(expires_at: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+    ((expires_at - now).num_seconds() + SECS_PER_DAY - 1).div_euclid(SECS_PER_DAY)
+}
+
+pub fn get_status() -> Result<SubscriptionStatus> {
+    let preferences = serde_json::to_value(leaf_sdk_desktop::get_preferences()?)?;
+    let bytes = |field: &str| {
+        preferences
+            .get(field)
+            .and_then(Value::as_f64)
+            .map(|value| value.max(0.0) as u64)
+            .unwrap_or_default()
+    };
+
+    let traffic = bytes("traffic");
+    let used_traffic = bytes("used_traffic");
+    let (remaining_bytes, percent_used) = if traffic > 0 {
+        (
+            Some(traffic.saturating_sub(used_traffic)),
+            Some(used_traffic as f64 * 100.0 / traffic as f64),
+        )
+    } else {
+        (None, None)
+    };
+
+    let expire_time = preferences
+        .get("expire_time")
+        .and_then(Value::as_str)
+        .filter(|time| !time.is_empty())
+        .map(str::to_string);
+    let expires_at = expire_time.as_deref().and_then(parse_expire_time);
+    let now = Utc::now();
+
+    Ok(SubscriptionStatus {
+        traffic,
+        used_traffic,
+        remaining_bytes,
+        percent_used,
+        days_until_expiry: expires_at.map(|time| days_until(time, now)),
+        expired: expires_at.is_some_and(|time| time <= now),
+        traffic_exhausted: traffic > 0 && used_traffic >= traffic,
+        expire_time,
+    })
+}
+
+pub fn get_settings<R: Runtime>(app: &AppHandle<R>) -> Result<QuotaSettings> {
+    persistence::load(app, QUOTA_FILE, QUOTA_KEY)
+}
+
+pub fn set_settings<R: Runtime>(
+    app: &AppHandle<R>,
+    enabled: bool,
+    traffic_thresholds: Vec<u8>,
+    expiry_days: Vec<u32>,
+) -> Result<QuotaSettings> {
+    if traffic_thresholds
+        .iter()
+        .any(|percent| *percent == 0 || *percent > 100)
+    {
+        return Err(anyhow!("traffic thresholds must be between 1 and 100"));
+    }
+
+    let mut settings = get_settings(app)?;
+    settings.enabled = enabled;
+    settings.traffic_thresholds = traffic_thresholds;
+    settings.expiry_days = expiry_days;
+
+    persistence::save(app, QUOTA_FILE, QUOTA_KEY, &settings)?;
+    Ok(settings)
+}
+
+// Returns the thresholds crossed by `status` and the notifications for the ones
+// not notified yet, at most one per category however many of its thresholds
+// were crossed since the last check.
+fn crossed_thresholds(
+    settings: &QuotaSettings,
+    status: &SubscriptionStatus,
+) -> (Vec<String>, Vec<String>) {
+    let mut notified = Vec::new();
+    let mut bodies = Vec::new();
+
+    if let Some(percent_used) = status.percent_used {
+        let crossed: Vec<String> = settings
+            .traffic_thresholds
+            .iter()
+            .filter(|threshold| percent_used >= f64::from(**threshold))
+            .map(|threshold| format!("traffic:{}", threshold))
+            .collect();
+        if crossed.iter().any(|key| !settings.notified.contains(key)) {
+            bodies.push(format!(
+                "You have used {:.0}% of your subscription traffic.",
+                percent_used
+            ));
+        }
+        notified.extend(crossed);
+    }
+
+    if let Some(days) = status.days_until_expiry {
+        let crossed: Vec<String> = settings
+            .expiry_days
+            .iter()
+            .filter(|threshold| days <= i64::from(**threshold))
+            .map(|threshold| format!("expiry:{}", threshold))
+            .collect();
+        if crossed.iter().any(|key| !settings.notified.contains(key)) {
+            bodies.push(if status.expired {
+                "Your subscription has expired.".to_string()
+            } else {
+                format!("Your subscription expires in {} day(s).", days.max(0))
+            });
+        }
+        notified.extend(crossed);
+    }
+
+    (notified, bodies)
+}
+
+/// Raises a notification for each category with thresholds crossed since the
+/// last check. Thresholds that are no longer crossed, for example after the
+/// subscription was renewed, are armed again.
+pub fn check<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
+    let _guard = CHECK_LOCK.lock();
+    let mut settings = get_settings(app)?;
+    if !settings.enabled {
+        return Ok(());
+    }
+
+    let (notified, bodies) = crossed_thresholds(&settings, &get_status()?);
+    for body in bodies {
+        util::notify(app, body);
+    }
+
+    if notified != settings.notified {
+        settings.notified = notified;
+        persistence::save(app, QUOTA_FILE, QUOTA_KEY, &settings)?;
+    }
+
+    Ok(())
+}
+
+pub fn start<R: Runtime>(app: AppHandle<R>) {
+    thread::spawn(move || loop {
+        if let Err(e) = check(&app) {
+            error!("Subscription quota check failed: {}", e);
+        }
+        thread::sleep(CHECK_INTERVAL);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(percent_used: Option<f64>, days_until_expiry: Option<i64>) -> SubscriptionStatus {
+        SubscriptionStatus {
+            traffic: 100,
+            used_traffic: 0,
+            remaining_bytes: None,
+            percent_used,
+            expire_time: None,
+            days_until_expiry,
+            expired: days_until_expiry.is_some_and(|days| days <= 0),
+            traffic_exhausted: false,
+        }
+    }
+
+    #[test]
+    fn counts_started_days_as_full() {
+        let now = Utc::now();
+        assert_eq!(days_until(now + chrono::Duration::hours(36), now), 2);
+        assert_eq!(days_until(now + chrono::Duration::days(1), now), 1);
+        assert_eq!(days_until(now + chrono::Duration::minutes(1), now), 1);
+        assert_eq!(days_until(now, now), 0);
+        assert_eq!(days_until(now - chrono::Duration::hours(36), now), -1);
+    }
+
+    #[test]
+    fn parses_expire_time_formats() {
+        assert!(parse_expire_time("2030-01-02T03:04:05Z").is_some());
+        assert!(parse_expire_time("2030-01-02 03:04:05").is_some());
+        assert!(parse_expire_time("2030-01-02").is_some());
+        assert!(parse_expire_time("soon").is_none());
+    }
+
+    #[test]
+    fn notifies_each_crossed_threshold_once() {
+        let mut settings = QuotaSettings::default();
+        let (notified, bodies) = crossed_thresholds(&settings, &status(Some(90.0), Some(5)));
+        assert_eq!(notified, ["traffic:80", "expiry:7"]);
+        assert_eq!(bodies.len(), 2);
+
+        settings.notified = notified;
+        let (notified, bodies) = crossed_thresholds(&settings, &status(Some(96.0), Some(5)));
+        assert_eq!(notified, ["traffic:80", "traffic:95", "expiry:7"]);
+        assert_eq!(bodies.len(), 1);
+    }
+
+    #[test]
+    fn notifies_once_per_category() {
+        let settings = QuotaSettings::default();
+        let (notified, bodies) = crossed_thresholds(&settings, &status(Some(97.0), Some(0)));
+        assert_eq!(
+            notified,
+            ["traffic:80", "traffic:95", "expiry:7", "expiry:1"]
+        );
+        assert_eq!(
+            bodies,
+            [
+                "You have used 97% of your subscription traffic.",
+                "Your subscription has expired."
+            ]
+        );
+    }
+}
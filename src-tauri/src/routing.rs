@@ -0,0 +1,343 @@
+use crate::helper::ValidationReport;
+use crate::leaf_config::{self, Outbound};
+use crate::{persistence, util};
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::IpAddr;
+use tauri::{AppHandle, Runtime};
+
+const ROUTING_FILE: &str = "routing.json";
+const ROUTING_KEY: &str = "rules";
+
+// Leaf protocols of the outbounds that direct and reject rules point at.
+const DIRECT_PROTOCOL: &str = "direct";
+const REJECT_PROTOCOL: &str = "drop";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+    Domain,
+    DomainSuffix,
+    DomainKeyword,
+    /// Leaf has no regular expression rules. Rules of this kind saved by
+    /// earlier builds still load, but are rejected on save and left out of
+    /// the config.
+    DomainRegex,
+    IpCidr,
+    PortRange,
+    Network,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+    Direct,
+    Proxy { outbound: String },
+    Reject,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoutingRule {
+    pub kind: RuleKind,
+    pub value: String,
+    pub action: RuleAction,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn is_cidr(value: &str) -> bool {
+    let (address, prefix) = match value.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (value, None),
+    };
+
+    let Ok(address) = address.parse::<IpAddr>() else {
+        return false;
+    };
+    let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+
+    match prefix {
+        Some(prefix) => prefix
+            .parse::<u8>()
+            .is_ok_and(|prefix| prefix <= max_prefix),
+        None => true,
+    }
+}
+
+fn parse_port_range(value: &str) -> Option<(u16, u16)> {
+    let (start, end) = value.split_once('-').unwrap_or((value, value));
+    let start = start.trim().parse::<u16>().ok()?;
+    let end = end.trim().parse::<u16>().ok()?;
+    (start > 0 && start <= end).then_some((start, end))
+}
+
+pub fn validate_rules(rules: &[RoutingRule]) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    for (index, rule) in rules.iter().enumerate() {
+        let field = format!("rules[{}]", index);
+        let value = rule.value.trim();
+
+        let error = match rule.kind {
+            RuleKind::Domain | RuleKind::DomainSuffix if !util::is_domain(value) => {
+                Some(format!("'{}' is not a valid domain", value))
+            }
+            RuleKind::DomainKeyword if value.is_empty() => {
+                Some("keyword must not be empty".to_string())
+            }
+            RuleKind::DomainRegex => Some(
+                "leaf does not support regular expression rules, use a domain keyword or suffix"
+                    .to_string(),
+            ),
+            RuleKind::IpCidr if !is_cidr(value) => {
+                Some(format!("'{}' is not an IP address or CIDR", value))
+            }
+            RuleKind::PortRange if parse_port_range(value).is_none() => Some(format!(
+                "'{}' is not a port or range such as 8000-9000",
+                value
+            )),
+            RuleKind::Network if value != "tcp" && value != "udp" => {
+                Some("network must be 'tcp' or 'udp'".to_string())
+            }
+            _ => None,
+        };
+        if let Some(error) = error {
+            report.error(&field, error);
+        }
+
+        if let RuleAction::Proxy { outbound } = &rule.action {
+            if outbound.trim().is_empty() {
+                report.error(&field, "proxy rules need an outbound");
+            }
+        }
+
+        let duplicate = rules[..index]
+            .iter()
+            .any(|other| other.kind == rule.kind && other.value.trim() == value);
+        if duplicate {
+            report.warning(&field, "an earlier rule already matches this value");
+        }
+    }
+
+    report
+}
+
+pub fn get_rules<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<RoutingRule>> {
+    persistence::load(app, ROUTING_FILE, ROUTING_KEY)
+}
+
+/// Validates `rules` and checks that the current leaf config has the
+/// outbounds they send traffic to.
+pub fn check_rules<R: Runtime>(
+    app: &AppHandle<R>,
+    rules: &[RoutingRule],
+) -> Result<ValidationReport> {
+    let report = validate_rules(rules);
+    if !report.is_valid() {
+        return Err(anyhow!("invalid routing rules: {}", report));
+    }
+    if let Some(base) = leaf_config::base(app)? {
+        to_leaf_rules(rules, &leaf_config::outbounds(&base))?;
+    }
+    Ok(report)
+}
+
+/// Validates `rules` and stores them in place of the current ones.
+pub fn save_rules<R: Runtime>(
+    app: &AppHandle<R>,
+    rules: &[RoutingRule],
+) -> Result<ValidationReport> {
+    let report = check_rules(app, rules)?;
+    persistence::save(app, ROUTING_FILE, ROUTING_KEY, &rules)?;
+    Ok(report)
+}
+
+pub fn set_rules<R: Runtime>(app: &AppHandle<R>, rules: String) -> Result<ValidationReport> {
+    let rules: Vec<RoutingRule> = serde_json::from_str(&rules)?;
+    save_rules(app, &rules)
+}
+
+// Finds the tag a rule sends its traffic to among the outbounds of the config.
+fn target<'a>(action: &'a RuleAction, outbounds: &'a [Outbound]) -> Result<&'a str> {
+    let by_protocol = |protocol: &str| {
+        outbounds
+            .iter()
+            .find(|outbound| outbound.protocol == protocol)
+            .map(|outbound| outbound.tag.as_str())
+            .ok_or_else(|| anyhow!("the config has no {} outbound", protocol))
+    };
+
+    match action {
+        RuleAction::Direct => by_protocol(DIRECT_PROTOCOL),
+        RuleAction::Reject => by_protocol(REJECT_PROTOCOL),
+        RuleAction::Proxy { outbound } => outbounds
+            .iter()
+            .find(|candidate| candidate.tag == outbound.trim())
+            .map(|candidate| candidate.tag.as_str())
+            .ok_or_else(|| anyhow!("the config has no outbound '{}'", outbound.trim())),
+    }
+}
+
+/// Renders one rule as an entry of the `rules` array of a leaf JSON config.
+pub fn to_leaf_rule(rule: &RoutingRule, outbounds: &[Outbound]) -> Result<Value> {
+    let value = rule.value.trim();
+    let (key, value) = match rule.kind {
+        RuleKind::Domain => ("domain", value.to_string()),
+        RuleKind::DomainSuffix => ("domainSuffix", value.to_string()),
+        RuleKind::DomainKeyword => ("domainKeyword", value.to_string()),
+        RuleKind::DomainRegex => {
+            return Err(anyhow!("leaf does not support regular expression rules"))
+        }
+        RuleKind::IpCidr => ("ip", value.to_string()),
+        RuleKind::PortRange => {
+            let (start, end) = parse_port_range(value)
+                .ok_or_else(|| anyhow!("'{}' is not a port or range", value))?;
+            ("portRange", format!("{}-{}", start, end))
+        }
+        RuleKind::Network => ("network", value.to_string()),
+    };
+    let target = target(&rule.action, outbounds)?;
+    Ok(json!({ key: [value], "target": target }))
+}
+
+/// Renders the enabled rules as entries of the `rules` array of a leaf JSON
+/// config, keeping their order so that the first match wins.
+pub fn to_leaf_rules(rules: &[RoutingRule], outbounds: &[Outbound]) -> Result<Vec<Value>> {
+    rules
+        .iter()
+        .filter(|rule| rule.enabled)
+        .map(|rule| to_leaf_rule(rule, outbounds))
+        .collect()
+}
+
+/// Like `to_leaf_rules`, but leaves out rules the config can no longer
+/// serve, such as a proxy rule whose outbound the subscription dropped.
+pub fn usable_leaf_rules(rules: &[RoutingRule], outbounds: &[Outbound]) -> Vec<Value> {
+    rules
+        .iter()
+        .filter(|rule| rule.enabled)
+        .filter_map(|rule| match to_leaf_rule(rule, outbounds) {
+            Ok(rule) => Some(rule),
+            Err(e) => {
+                warn!("Skipping routing rule for '{}': {}", rule.value.trim(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn leaf_rules<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<Value>> {
+    let base = leaf_config::base(app)?.ok_or_else(|| anyhow!("no leaf config yet"))?;
+    Ok(usable_leaf_rules(
+        &get_rules(app)?,
+        &leaf_config::outbounds(&base),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(kind: RuleKind, value: &str) -> RoutingRule {
+        RoutingRule {
+            kind,
+            value: value.to_string(),
+            action: RuleAction::Direct,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn parses_cidrs_and_port_ranges() {
+        assert!(is_cidr("10.0.0.0/8"));
+        assert!(is_cidr("fd00::/8"));
+        assert!(is_cidr("192.168.1.1"));
+        assert!(!is_cidr("10.0.0.0/33"));
+        assert!(!is_cidr("example.com/8"));
+
+        assert_eq!(parse_port_range("443"), Some((443, 443)));
+        assert_eq!(parse_port_range("8000 - 9000"), Some((8000, 9000)));
+        assert_eq!(parse_port_range("0"), None);
+        assert_eq!(parse_port_range("9000-8000"), None);
+    }
+
+    #[test]
+    fn reports_invalid_and_duplicate_rules() {
+        let mut proxy = rule(RuleKind::Domain, "example.com");
+        proxy.action = RuleAction::Proxy {
+            outbound: " ".to_string(),
+        };
+        let rules = [
+            rule(RuleKind::DomainSuffix, "example.com"),
+            rule(RuleKind::DomainSuffix, " example.com "),
+            rule(RuleKind::DomainRegex, "example"),
+            rule(RuleKind::Network, "icmp"),
+            proxy,
+        ];
+
+        let report = validate_rules(&rules);
+        let fields: Vec<&str> = report.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["rules[2]", "rules[3]", "rules[4]"]);
+        assert_eq!(report.warnings[0].field, "rules[1]");
+    }
+
+    #[test]
+    fn skips_disabled_rules() {
+        let outbounds = [Outbound {
+            tag: "Direct".to_string(),
+            protocol: DIRECT_PROTOCOL.to_string(),
+        }];
+        let mut disabled = rule(RuleKind::PortRange, "22");
+        disabled.enabled = false;
+        let rules = [rule(RuleKind::PortRange, "443"), disabled];
+
+        assert_eq!(
+            to_leaf_rules(&rules, &outbounds).unwrap(),
+            [json!({ "portRange": ["443-443"], "target": "Direct" })]
+        );
+    }
+
+    #[test]
+    fn writes_normalized_port_ranges() {
+        let outbounds = [Outbound {
+            tag: "Direct".to_string(),
+            protocol: DIRECT_PROTOCOL.to_string(),
+        }];
+        assert_eq!(
+            to_leaf_rule(&rule(RuleKind::PortRange, " 8000 - 9000 "), &outbounds).unwrap(),
+            json!({ "portRange": ["8000-9000"], "target": "Direct" })
+        );
+    }
+
+    #[test]
+    fn skips_rules_the_config_cannot_serve() {
+        let outbounds = [Outbound {
+            tag: "Direct".to_string(),
+            protocol: DIRECT_PROTOCOL.to_string(),
+        }];
+        let mut stale = rule(RuleKind::Domain, "example.com");
+        stale.action = RuleAction::Proxy {
+            outbound: "Gone".to_string(),
+        };
+        let mut reject = rule(RuleKind::Domain, "ads.example.com");
+        reject.action = RuleAction::Reject;
+        let rules = [
+            stale,
+            reject,
+            rule(RuleKind::DomainRegex, ".*"),
+            rule(RuleKind::DomainSuffix, "example.org"),
+        ];
+
+        assert!(to_leaf_rules(&rules, &outbounds).is_err());
+        assert_eq!(
+            usable_leaf_rules(&rules, &outbounds),
+            [json!({ "domainSuffix": ["example.org"], "target": "Direct" })]
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{
+    fetch_options, persistence, policy, reload_leaf_from_app, subscriptions, util,
+    LATEST_CORE_STATE, LATEST_LEAF_STATE,
+};
+use anyhow::{anyhow, Result};
+use leaf_sdk_desktop::{CoreState, LeafState};
+use log::{error, info};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Runtime};
+
+const SCHEDULER_FILE: &str = "scheduler.json";
+const SCHEDULER_KEY: &str = "scheduler";
+
+// The command whose policy entry also covers scheduled refreshes.
+const UPDATE_COMMAND: &str = "auto_update_subscription";
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(300);
+const BUSY_RETRY_SECS: u64 = 60;
+const BASE_RETRY_SECS: u64 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SchedulerSettings {
+    pub enabled: bool,
+    pub interval_minutes: u64,
+    pub jitter_minutes: u64,
+    pub next_run: Option<u64>,
+    pub failures: u32,
+}
+
+impl Default for SchedulerSettings {
+    fn default() -> Self {
+        SchedulerSettings {
+            enabled: false,
+            interval_minutes: 360,
+            jitter_minutes: 30,
+            next_run: None,
+            failures: 0,
+        }
+    }
+}
+
+static SETTINGS: Lazy<Mutex<Option<SchedulerSettings>>> = Lazy::new(|| Mutex::new(None));
+
+// Spreads runs of many clients over +/- `jitter_secs` without pulling in a
+// random number generator.
+fn jitter(jitter_secs: u64) -> i64 {
+    if jitter_secs == 0 {
+        return 0;
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos() as u64)
+        .unwrap_or_default();
+    (nanos % (2 * jitter_secs + 1)) as i64 - jitter_secs as i64
+}
+
+fn next_regular_run(settings: &SchedulerSettings) -> u64 {
+    let interval = (settings.interval_minutes * 60) as i64;
+    let offset = interval + jitter(settings.jitter_minutes * 60);
+    util::now() + offset.max(BASE_RETRY_SECS as i64) as u64
+}
+
+// Exponential backoff that never waits longer than the regular interval.
+fn next_retry(settings: &SchedulerSettings) -> u64 {
+    let exponent = settings.failures.saturating_sub(1).min(16);
+    let delay = BASE_RETRY_SECS.saturating_mul(1 << exponent);
+    util::now() + delay.min(settings.interval_minutes * 60)
+}
+
+fn load<R: Runtime>(app: &AppHandle<R>) -> Result<SchedulerSettings> {
+    let mut cached = SETTINGS.lock();
+    if let Some(settings) = cached.as_ref() {
+        return Ok(settings.clone());
+    }
+
+    let settings: SchedulerSettings = persistence::load(app, SCHEDULER_FILE, SCHEDULER_KEY)?;
+    *cached = Some(settings.clone());
+    Ok(settings)
+}
+
+fn save<R: Runtime>(app: &AppHandle<R>, settings: SchedulerSettings) -> Result<()> {
+    persistence::save(app, SCHEDULER_FILE, SCHEDULER_KEY, &settings)?;
+    *SETTINGS.lock() = Some(settings);
+    Ok(())
+}
+
+pub fn get_settings<R: Runtime>(app: &AppHandle<R>) -> Result<SchedulerSettings> {
+    load(app)
+}
+
+pub fn set_settings<R: Runtime>(
+    app: &AppHandle<R>,
+    enabled: bool,
+    interval_minutes: u64,
+    jitter_minutes: u64,
+) -> Result<SchedulerSettings> {
+    if interval_minutes < 15 {
+        return Err(anyhow!("interval must be at least 15 minutes"));
+    }
+    if jitter_minutes * 2 > interval_minutes {
+        return Err(anyhow!("jitter must be at most half of the interval"));
+    }
+
+    let mut settings = load(app)?;
+    settings.enabled = enabled;
+    settings.interval_minutes = interval_minutes;
+    settings.jitter_minutes = jitter_minutes;
+    settings.failures = 0;
+    settings.next_run = Some(next_regular_run(&settings));
+
+    save(app, settings.clone())?;
+    Ok(settings)
+}
+
+fn is_busy() -> bool {
+    matches!(*LATEST_CORE_STATE.lock(), Some(CoreState::STARTING))
+        || matches!(*LATEST_LEAF_STATE.lock(), Some(LeafState::STARTING))
+}
+
+// Runs one update and waits for its final state.
+fn run_update<R: Runtime>(app: &AppHandle<R>, client_id: String) -> Result<()> {
+    util::run_subscription_update(app, UPDATE_TIMEOUT, |callback| {
+        fetch_options::update_subscription(app, client_id, move |state, options| {
+            callback(state, Some(options))
+        })
+    })
+}
+
+fn tick<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
+    let mut settings = load(app)?;
+    if !settings.enabled
+        || policy::check_update(true).is_err()
+        || policy::check_command(UPDATE_COMMAND).is_err()
+    {
+        return Ok(());
+    }
+
+    let Some(next_run) = settings.next_run else {
+        settings.next_run = Some(next_regular_run(&settings));
+        return save(app, settings);
+    };
+    if util::now() < next_run {
+        return Ok(());
+    }
+
+    if is_busy() {
+        info!("Connection in progress, postponing subscription refresh");
+        settings.next_run = Some(util::now() + BUSY_RETRY_SECS);
+        return save(app, settings);
+    }
+
+    let Some(client_id) = subscriptions::current_client_id()? else {
+        info!("No subscription configured, skipping refresh");
+        settings.next_run = Some(next_regular_run(&settings));
+        return save(app, settings);
+    };
+
+    info!("Refreshing subscription");
+    match run_update(app, client_id) {
+        Ok(()) => {
+            settings.failures = 0;
+            settings.next_run = Some(next_regular_run(&settings));
+            if let Err(e) = reload_leaf_from_app(app) {
+                error!("Failed to reload leaf after refreshing subscription: {}", e);
+            }
+        }
+        Err(e) => {
+            settings.failures += 1;
+            settings.next_run = Some(next_retry(&settings));
+            error!(
+                "Scheduled subscription refresh failed ({} in a row): {}",
+                settings.failures, e
+            );
+        }
+    }
+
+    // The settings may have been changed while the update was running.
+    let mut latest = load(app)?;
+    latest.failures = settings.failures;
+    if latest.next_run == Some(next_run) {
+        latest.next_run = settings.next_run;
+    }
+    save(app, latest)
+}
+
+pub fn start<R: Runtime>(app: AppHandle<R>) {
+    thread::spawn(move || loop {
+        if let Err(e) = tick(&app) {
+            error!("Subscription scheduler error: {}", e);
+        }
+        thread::sleep(POLL_INTERVAL);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_disabled_by_default() {
+        assert!(!SchedulerSettings::default().enabled);
+    }
+
+    #[test]
+    fn regular_runs_stay_within_the_jitter() {
+        let settings = SchedulerSettings::default();
+        let now = util::now();
+        let next = next_regular_run(&settings);
+        assert!(next >= now + (360 - 30) * 60);
+        assert!(next <= util::now() + (360 + 30) * 60);
+    }
+
+    #[test]
+    fn retries_back_off_up_to_the_interval() {
+        let mut settings = SchedulerSettings {
+            interval_minutes: 60,
+            ..Default::default()
+        };
+        let assert_delay = |settings: &SchedulerSettings, secs: u64| {
+            let before = util::now();
+            let next = next_retry(settings);
+            assert!(next >= before + secs && next <= util::now() + secs);
+        };
+
+        settings.failures = 1;
+        assert_delay(&settings, BASE_RETRY_SECS);
+        settings.failures = 3;
+        assert_delay(&settings, 4 * BASE_RETRY_SECS);
+        settings.failures = 30;
+        assert_delay(&settings, 60 * 60);
+    }
+}
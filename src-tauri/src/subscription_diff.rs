@@ -0,0 +1,296 @@
+use crate::{leaf_config, persistence, subscriptions, util};
+use anyhow::Result;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use tauri::{AppHandle, Emitter, Runtime};
+
+const DIFF_FILE: &str = "subscription_diff.json";
+const DIFFS_KEY: &str = "diffs";
+const MAX_DIFFS: usize = 20;
+
+/// What a subscription config defines, as far as its updates are compared.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigSnapshot {
+    /// Outbound tag to outbound protocol.
+    pub outbounds: BTreeMap<String, String>,
+    /// Select group tag to its members, in order.
+    pub selects: BTreeMap<String, Vec<String>>,
+    /// The routing rules of the config, in order.
+    pub rules: Vec<Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SelectChange {
+    pub group: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SubscriptionDiff {
+    pub timestamp: u64,
+    pub subscription: Option<String>,
+    pub outbounds_added: Vec<String>,
+    pub outbounds_removed: Vec<String>,
+    pub outbounds_renamed: Vec<Rename>,
+    pub select_changes: Vec<SelectChange>,
+    #[serde(default)]
+    pub rules_added: Vec<Value>,
+    #[serde(default)]
+    pub rules_removed: Vec<Value>,
+}
+
+impl SubscriptionDiff {
+    fn is_empty(&self) -> bool {
+        self.outbounds_added.is_empty()
+            && self.outbounds_removed.is_empty()
+            && self.outbounds_renamed.is_empty()
+            && self.select_changes.is_empty()
+            && self.rules_added.is_empty()
+            && self.rules_removed.is_empty()
+    }
+}
+
+impl ConfigSnapshot {
+    /// Reads the outbounds and select groups a leaf config defines.
+    pub fn of(config: &Value) -> Self {
+        let mut snapshot = ConfigSnapshot::default();
+        for outbound in leaf_config::outbounds(config) {
+            snapshot
+                .outbounds
+                .insert(outbound.tag.clone(), outbound.protocol.clone());
+        }
+
+        for outbound in config["outbounds"].as_array().into_iter().flatten() {
+            let (Some(tag), Some("select")) =
+                (outbound["tag"].as_str(), outbound["protocol"].as_str())
+            else {
+                continue;
+            };
+            let members = outbound["settings"]["actors"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|actor| actor.as_str().map(str::to_string))
+                .collect();
+            snapshot.selects.insert(tag.to_string(), members);
+        }
+
+        snapshot.rules = config["router"]["rules"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        snapshot
+    }
+}
+
+// An outbound counts as renamed when a removed and an added outbound of the
+// same type take the same position in a select group.
+fn find_renames(
+    old: &ConfigSnapshot,
+    new: &ConfigSnapshot,
+    removed: &BTreeSet<String>,
+    added: &BTreeSet<String>,
+) -> Vec<Rename> {
+    let mut renames: Vec<Rename> = Vec::new();
+    for (group, old_members) in &old.selects {
+        let Some(new_members) = new.selects.get(group) else {
+            continue;
+        };
+        for (from, to) in old_members.iter().zip(new_members) {
+            let taken = renames
+                .iter()
+                .any(|rename| &rename.from == from || &rename.to == to);
+            if removed.contains(from)
+                && added.contains(to)
+                && old.outbounds.get(from) == new.outbounds.get(to)
+                && !taken
+            {
+                renames.push(Rename {
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+            }
+        }
+    }
+    renames
+}
+
+fn compare(old: &ConfigSnapshot, new: &ConfigSnapshot) -> SubscriptionDiff {
+    let old_tags: BTreeSet<String> = old.outbounds.keys().cloned().collect();
+    let new_tags: BTreeSet<String> = new.outbounds.keys().cloned().collect();
+    let removed: BTreeSet<String> = old_tags.difference(&new_tags).cloned().collect();
+    let added: BTreeSet<String> = new_tags.difference(&old_tags).cloned().collect();
+
+    let renames = find_renames(old, new, &removed, &added);
+    let renamed_from: BTreeSet<&String> = renames.iter().map(|rename| &rename.from).collect();
+    let renamed_to: BTreeSet<&String> = renames.iter().map(|rename| &rename.to).collect();
+
+    let groups: BTreeSet<&String> = old.selects.keys().chain(new.selects.keys()).collect();
+    let select_changes = groups
+        .into_iter()
+        .filter_map(|group| {
+            let empty = Vec::new();
+            let old_members = old.selects.get(group).unwrap_or(&empty);
+            let new_members = new.selects.get(group).unwrap_or(&empty);
+            let change = SelectChange {
+                group: group.clone(),
+                added: new_members
+                    .iter()
+                    .filter(|member| !old_members.contains(member))
+                    .cloned()
+                    .collect(),
+                removed: old_members
+                    .iter()
+                    .filter(|member| !new_members.contains(member))
+                    .cloned()
+                    .collect(),
+            };
+            (!change.added.is_empty() || !change.removed.is_empty()).then_some(change)
+        })
+        .collect();
+
+    SubscriptionDiff {
+        timestamp: util::now(),
+        subscription: None,
+        outbounds_added: added
+            .iter()
+            .filter(|tag| !renamed_to.contains(tag))
+            .cloned()
+            .collect(),
+        outbounds_removed: removed
+            .iter()
+            .filter(|tag| !renamed_from.contains(tag))
+            .cloned()
+            .collect(),
+        outbounds_renamed: renames,
+        select_changes,
+        rules_added: new
+            .rules
+            .iter()
+            .filter(|rule| !old.rules.contains(rule))
+            .cloned()
+            .collect(),
+        rules_removed: old
+            .rules
+            .iter()
+            .filter(|rule| !new.rules.contains(rule))
+            .cloned()
+            .collect(),
+    }
+}
+
+pub fn list<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<SubscriptionDiff>> {
+    let mut diffs: Vec<SubscriptionDiff> = persistence::load(app, DIFF_FILE, DIFFS_KEY)?;
+    diffs.reverse();
+    Ok(diffs)
+}
+
+// Stores the difference between two subscription configs, if any.
+fn record<R: Runtime>(
+    app: &AppHandle<R>,
+    previous: &Value,
+    config: &Value,
+) -> Result<Option<SubscriptionDiff>> {
+    let mut diff = compare(&ConfigSnapshot::of(previous), &ConfigSnapshot::of(config));
+    if diff.is_empty() {
+        return Ok(None);
+    }
+    diff.subscription = subscriptions::list(app)?.active;
+
+    let mut diffs: Vec<SubscriptionDiff> = persistence::load(app, DIFF_FILE, DIFFS_KEY)?;
+    diffs.push(diff.clone());
+    if diffs.len() > MAX_DIFFS {
+        diffs.drain(..diffs.len() - MAX_DIFFS);
+    }
+    persistence::save(app, DIFF_FILE, DIFFS_KEY, &diffs)?;
+
+    Ok(Some(diff))
+}
+
+/// Compares a freshly installed subscription config with the one it replaced
+/// and emits `subscription-diff-event` when they differ. The first config
+/// only becomes the baseline.
+pub fn on_update<R: Runtime>(app: &AppHandle<R>, adopted: &leaf_config::Adopted) {
+    let Some(previous) = &adopted.previous else {
+        return;
+    };
+    match record(app, previous, &adopted.config) {
+        Ok(Some(diff)) => {
+            info!("Subscription config changed: {:?}", diff);
+            let _ = app.emit("subscription-diff-event", diff);
+        }
+        Ok(None) => {}
+        Err(e) => error!("Failed to compare subscription configs: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(servers: &[(&str, &str)], rules: Value) -> Value {
+        let mut outbounds: Vec<Value> = servers
+            .iter()
+            .map(|(tag, protocol)| json!({ "tag": tag, "protocol": protocol }))
+            .collect();
+        let actors: Vec<&str> = servers.iter().map(|(tag, _)| *tag).collect();
+        outbounds.push(json!({
+            "tag": "proxy",
+            "protocol": "select",
+            "settings": { "actors": actors }
+        }));
+        json!({ "outbounds": outbounds, "router": { "rules": rules } })
+    }
+
+    #[test]
+    fn reads_select_members_from_the_config() {
+        let snapshot = ConfigSnapshot::of(&config(&[("a", "trojan"), ("b", "vmess")], json!([])));
+        assert_eq!(snapshot.selects["proxy"], ["a", "b"]);
+        assert_eq!(snapshot.outbounds["b"], "vmess");
+        assert_eq!(snapshot.outbounds["proxy"], "select");
+    }
+
+    #[test]
+    fn detects_renames_by_position_and_protocol() {
+        let old = ConfigSnapshot::of(&config(&[("a", "trojan"), ("b", "vmess")], json!([])));
+        let new = ConfigSnapshot::of(&config(
+            &[("a2", "trojan"), ("c", "shadowsocks")],
+            json!([]),
+        ));
+        let diff = compare(&old, &new);
+
+        assert_eq!(
+            diff.outbounds_renamed,
+            [Rename {
+                from: "a".to_string(),
+                to: "a2".to_string()
+            }]
+        );
+        assert_eq!(diff.outbounds_added, ["c"]);
+        assert_eq!(diff.outbounds_removed, ["b"]);
+        assert_eq!(diff.select_changes[0].added, ["a2", "c"]);
+    }
+
+    #[test]
+    fn reports_rule_changes() {
+        let direct = json!({ "domainSuffix": ["lan"], "target": "direct" });
+        let proxy = json!({ "domain": ["example.com"], "target": "proxy" });
+        let old = ConfigSnapshot::of(&config(&[("a", "trojan")], json!([direct.clone()])));
+        let new = ConfigSnapshot::of(&config(&[("a", "trojan")], json!([proxy.clone()])));
+        let diff = compare(&old, &new);
+
+        assert_eq!(diff.rules_added, [proxy]);
+        assert_eq!(diff.rules_removed, [direct]);
+        assert!(compare(&old, &old).is_empty());
+    }
+}
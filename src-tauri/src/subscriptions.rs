@@ -0,0 +1,191 @@
+use crate::{persistence, util};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Runtime};
+
+const SUBSCRIPTIONS_FILE: &str = "subscriptions.json";
+const SUBSCRIPTIONS_KEY: &str = "subscriptions";
+const DEFAULT_NAME: &str = "Default";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SubscriptionEntry {
+    pub name: String,
+    pub client_id: String,
+    pub last_update_time: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SubscriptionRegistry {
+    pub entries: Vec<SubscriptionEntry>,
+    pub active: Option<String>,
+}
+
+impl SubscriptionRegistry {
+    fn find(&self, name: &str) -> Result<&SubscriptionEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| anyhow!("subscription '{}' does not exist", name))
+    }
+
+    // Records an update of the installed `client_id` on its entry. Nothing is
+    // recorded for a client id the registry does not know, such as one
+    // installed by an offline import.
+    fn record(&mut self, client_id: Option<&str>, error: Option<String>, now: u64) -> bool {
+        let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| Some(entry.client_id.as_str()) == client_id)
+        else {
+            return false;
+        };
+
+        if error.is_none() {
+            entry.last_update_time = Some(now);
+            self.active = Some(entry.name.clone());
+        }
+        entry.last_error = error;
+        true
+    }
+}
+
+// The registry starts out with the subscription that was configured before
+// multiple subscriptions were supported.
+fn load<R: Runtime>(app: &AppHandle<R>) -> Result<SubscriptionRegistry> {
+    let mut registry: SubscriptionRegistry =
+        persistence::load(app, SUBSCRIPTIONS_FILE, SUBSCRIPTIONS_KEY)?;
+
+    if registry.entries.is_empty() {
+        if let Some(client_id) = current_client_id()? {
+            registry.entries.push(SubscriptionEntry {
+                name: DEFAULT_NAME.to_string(),
+                client_id,
+                last_update_time: None,
+                last_error: None,
+            });
+            registry.active = Some(DEFAULT_NAME.to_string());
+        }
+    }
+
+    Ok(registry)
+}
+
+fn save<R: Runtime>(app: &AppHandle<R>, registry: &SubscriptionRegistry) -> Result<()> {
+    persistence::save(app, SUBSCRIPTIONS_FILE, SUBSCRIPTIONS_KEY, registry)
+}
+
+pub fn list<R: Runtime>(app: &AppHandle<R>) -> Result<SubscriptionRegistry> {
+    load(app)
+}
+
+pub fn add<R: Runtime>(app: &AppHandle<R>, name: String, client_id: String) -> Result<()> {
+    let mut registry = load(app)?;
+    let name = name.trim().to_string();
+    let client_id = client_id.trim().to_string();
+
+    if name.is_empty() || client_id.is_empty() {
+        return Err(anyhow!("name and client id must not be empty"));
+    }
+    if registry.entries.iter().any(|entry| entry.name == name) {
+        return Err(anyhow!("subscription '{}' already exists", name));
+    }
+
+    registry.entries.push(SubscriptionEntry {
+        name,
+        client_id,
+        last_update_time: None,
+        last_error: None,
+    });
+    save(app, &registry)
+}
+
+pub fn remove<R: Runtime>(app: &AppHandle<R>, name: String) -> Result<()> {
+    let mut registry = load(app)?;
+    if registry.active.as_deref() == Some(name.as_str()) {
+        return Err(anyhow!("the active subscription cannot be removed"));
+    }
+
+    registry.find(&name)?;
+    registry.entries.retain(|entry| entry.name != name);
+    save(app, &registry)
+}
+
+pub fn client_id<R: Runtime>(app: &AppHandle<R>, name: &str) -> Result<String> {
+    Ok(load(app)?.find(name)?.client_id.clone())
+}
+
+/// Marks `name` as the active subscription once its config is installed.
+pub fn set_active<R: Runtime>(app: &AppHandle<R>, name: &str) -> Result<()> {
+    let mut registry = load(app)?;
+    registry.find(name)?;
+    registry.active = Some(name.to_string());
+    save(app, &registry)
+}
+
+pub fn current_client_id() -> Result<Option<String>> {
+    let preferences = serde_json::to_value(leaf_sdk_desktop::get_preferences()?)?;
+    Ok(preferences
+        .get("client_id")
+        .and_then(Value::as_str)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string))
+}
+
+/// Records the outcome of the latest subscription update. A successful update
+/// also makes the entry holding the fetched client id the active one.
+pub fn record_update<R: Runtime>(app: &AppHandle<R>, error: Option<String>) -> Result<()> {
+    let mut registry = load(app)?;
+    if registry.record(current_client_id()?.as_deref(), error, util::now()) {
+        save(app, &registry)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> SubscriptionRegistry {
+        let entry = |name: &str, client_id: &str| SubscriptionEntry {
+            name: name.to_string(),
+            client_id: client_id.to_string(),
+            last_update_time: None,
+            last_error: None,
+        };
+        SubscriptionRegistry {
+            entries: vec![entry("Home", "home-id"), entry("Work", "work-id")],
+            active: Some("Home".to_string()),
+        }
+    }
+
+    #[test]
+    fn success_activates_the_fetched_entry() {
+        let mut registry = registry();
+        assert!(registry.record(Some("work-id"), None, 42));
+        assert_eq!(registry.active.as_deref(), Some("Work"));
+        assert_eq!(registry.entries[1].last_update_time, Some(42));
+    }
+
+    #[test]
+    fn failure_keeps_the_active_entry() {
+        let mut registry = registry();
+        assert!(registry.record(Some("work-id"), Some("timeout".to_string()), 42));
+        assert_eq!(registry.active.as_deref(), Some("Home"));
+        assert_eq!(registry.entries[1].last_error.as_deref(), Some("timeout"));
+        assert_eq!(registry.entries[1].last_update_time, None);
+    }
+
+    #[test]
+    fn ignores_client_ids_outside_the_registry() {
+        let mut registry = registry();
+        assert!(!registry.record(Some("imported-id"), None, 42));
+        assert!(!registry.record(None, None, 42));
+        assert_eq!(registry.active.as_deref(), Some("Home"));
+        assert!(registry
+            .entries
+            .iter()
+            .all(|e| e.last_update_time.is_none()));
+    }
+}
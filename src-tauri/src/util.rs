@@ -0,0 +1,108 @@
+use crate::fetch_options::EffectiveFetchOptions;
+use crate::handle_subscription_state;
+use anyhow::{anyhow, Result};
+use leaf_sdk_desktop::SubscriptionState;
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Runtime};
+use tauri_plugin_notification::NotificationExt;
+
+/// Length of a client id, which is a UUID such as
+/// `123e4567-e89b-12d3-a456-426614174000`.
+pub const CLIENT_ID_LEN: usize = 36;
+
+/// Whether `value` has the UUID format of a client id.
+pub fn is_client_id(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    value.len() == CLIENT_ID_LEN
+        && groups.iter().map(|group| group.len()).eq([8, 4, 4, 4, 12])
+        && groups
+            .iter()
+            .all(|group| group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Whether `value` is a domain name such as `corp.example`.
+pub fn is_domain(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= 253
+        && value.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Current unix time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Shows a desktop notification, ignoring platforms that refuse it.
+pub fn notify<R: Runtime>(app: &AppHandle<R>, body: String) {
+    let _ = app
+        .notification()
+        .builder()
+        .title("Leaf VPN")
+        .body(body)
+        .show();
+}
+
+/// Callback for the states of a subscription update, along with the fetch
+/// options it ran with when it fetched from the network.
+pub type SubscriptionCallback =
+    Box<dyn Fn(SubscriptionState, Option<EffectiveFetchOptions>) + Send + Sync>;
+
+/// Runs one subscription update or import, started by `start` with the
+/// callback to hand to the library, and waits for its final state.
+pub fn run_subscription_update<R, F>(app: &AppHandle<R>, timeout: Duration, start: F) -> Result<()>
+where
+    R: Runtime,
+    F: FnOnce(SubscriptionCallback) -> Result<()>,
+{
+    let (sender, receiver) = mpsc::channel();
+    let app = app.clone();
+
+    start(Box::new(move |state, options| {
+        let sender = sender.clone();
+        handle_subscription_state(&app, state, options, move |state| {
+            let outcome = match state {
+                SubscriptionState::SUCCESS => Ok(()),
+                SubscriptionState::ERROR { error } => Err(anyhow!("{}", error)),
+                _ => return,
+            };
+            let _ = sender.send(outcome);
+        });
+    }))?;
+
+    receiver
+        .recv_timeout(timeout)
+        .map_err(|_| anyhow!("timed out after {} seconds", timeout.as_secs()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ids_are_uuids() {
+        assert!(is_client_id("123e4567-e89b-12d3-a456-426614174000"));
+        assert!(is_client_id("123E4567-E89B-12D3-A456-426614174000"));
+        assert!(!is_client_id("123e4567e89b12d3a456426614174000"));
+        assert!(!is_client_id("123e4567-e89b-12d3-a456-42661417400"));
+        assert!(!is_client_id("123e4567-e89b-12d3-a456-4266141740000"));
+        assert!(!is_client_id("123e4567-e89b-12d3-a456-42661417400g"));
+        assert!(!is_client_id("12345678-1234-1234-12345-12345678901"));
+        assert!(!is_client_id("deadbeef"));
+    }
+
+    #[test]
+    fn checks_domain_labels() {
+        assert!(is_domain("corp.example"));
+        assert!(!is_domain("corp..example"));
+        assert!(!is_domain("corp example"));
+        assert!(!is_domain(&"a".repeat(64)));
+    }
+}
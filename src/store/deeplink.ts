@@ -1,11 +1,10 @@
 import { defineStore } from 'pinia';
-import { UnlistenFn } from '@tauri-apps/api/event';
-import { getCurrent, onOpenUrl } from '@tauri-apps/plugin-deep-link';
+import { listen, UnlistenFn } from '@tauri-apps/api/event';
 import { invoke } from '@tauri-apps/api/core';
-import { DeepLinkState } from '../types/types.ts';
+import { nextTick } from 'vue';
+import { DeepLinkEvent, DeepLinkState } from '../types/types.ts';
 import { useSubscriptionStore } from './subscription';
-import delay from 'delay';
-import { warn, info } from '../utils/logger';
+import { info } from '../utils/logger';
 
 export const useDeeplinkStore = defineStore('deeplink', {
   state: () => ({
@@ -18,37 +17,13 @@ export const useDeeplinkStore = defineStore('deeplink', {
 
   actions: {
     async init(): Promise<void> {
-      this.unlistenFn = await onOpenUrl(async (urls: string[]) => {
-        if (urls && urls.length > 0) {
-          try {
-            await invoke('show_main_window');
-            await this.processUrl(urls[0]);
-          } catch (err) {
-            await invoke('show_main_window');
-            this.deeplinkState = DeepLinkState.UnknownError;
-            this.errorMessage =
-              err instanceof Error ? err.message : String(err);
-          }
+      // Links are parsed and run by the backend, which reports each one here.
+      this.unlistenFn = await listen<DeepLinkEvent>(
+        'deep-link-event',
+        async (event) => {
+          await this.handleEvent(event.payload);
         }
-      });
-
-      try {
-        const startUrls = await getCurrent();
-        if (startUrls && startUrls.length > 0) {
-          const url = startUrls[0];
-          try {
-            await invoke('show_main_window');
-            await this.processUrl(url);
-          } catch (err) {
-            await invoke('show_main_window');
-            this.deeplinkState = DeepLinkState.UnknownError;
-            this.errorMessage =
-              err instanceof Error ? err.message : String(err);
-          }
-        }
-      } catch {
-        warn('Failed to get startup deep link via getCurrent()');
-      }
+      );
     },
 
     async retryInstall(): Promise<void> {
@@ -68,63 +43,27 @@ export const useDeeplinkStore = defineStore('deeplink', {
       }
     },
 
-    async processUrl(url: string): Promise<void> {
-      info('Processing url:', url);
-
-      const subscriptionStore = useSubscriptionStore();
-
-      this.deeplinkState = DeepLinkState.Received;
-      this.loadingMessage = 'Received deep link...';
+    async handleEvent(event: DeepLinkEvent): Promise<void> {
+      info('Deep link event:', event);
 
-      await delay(1000);
-
-      const parsed = new URL(url);
-      if (parsed.protocol.replace(':', '') !== 'leafvpn') {
-        throw new Error(
-          `Invalid scheme: expected 'leafvpn', got '${parsed.protocol}'`
-        );
-      }
-      if (parsed.hostname !== 'install') {
-        throw new Error(
-          `Invalid host: expected 'install', got '${parsed.hostname}'`
-        );
+      if (event.error) {
+        await invoke('show_main_window');
+        this.deeplinkState = DeepLinkState.UnknownError;
+        this.errorMessage = event.error;
+        return;
       }
 
-      const params = parsed.searchParams;
-      const profileParam = params.get('profile');
-      if (!profileParam) throw new Error("Missing 'profile' query parameter");
+      if (event.action?.type !== 'install') return;
 
-      // try normal base64 then URL-safe
-      let decoded: string | null = null;
-      try {
-        decoded = atob(profileParam);
-      } catch {
-        // URL-safe base64 -> replace - _ and pad
-        const padded = profileParam.replace(/-/g, '+').replace(/_/g, '/');
-        const pad = padded.length % 4;
-        const paddedFinal = pad === 0 ? padded : padded + '='.repeat(4 - pad);
-        try {
-          decoded = atob(paddedFinal);
-        } catch {
-          throw new Error('Base64 decode failed');
-        }
-      }
-
-      if (!decoded || decoded.trim() === '') {
-        throw new Error('Decoded profile is empty');
-      }
-
-      // size check (UUID v4 is always 36 characters)
-      if (decoded.length > 36) {
-        throw new Error('Decoded profile is unexpectedly large');
-      }
+      await invoke('show_main_window');
+      this.deeplinkState = DeepLinkState.Received;
+      this.loadingMessage = 'Received deep link...';
+      await nextTick();
 
-      // success path: trigger same behaviour as 'parsed' event
+      // the backend is already fetching the profile
       this.deeplinkState = DeepLinkState.Loading;
       this.loadingMessage = 'Processing profile...';
-      this.currentProfile = decoded;
-
-      await subscriptionStore.updateSubscription(decoded);
+      this.currentProfile = event.action.profile;
     },
   },
 });
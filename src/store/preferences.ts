@@ -1,9 +1,27 @@
 import { defineStore } from 'pinia';
-import { LeafPreferences, UpdateLeafPreferences } from '../types/types';
+import {
+  LeafPreferences,
+  PreferencesError,
+  PreferencesUpdate,
+  UpdateLeafPreferences,
+} from '../types/types';
 import ApiClient from '../api/ApiClient';
 import { invoke } from '@tauri-apps/api/core';
 import { Utils } from '../utils/Utils';
 
+export function describePreferencesError(e: unknown): string {
+  const error = e as PreferencesError;
+  if (error?.type === 'invalid') {
+    return error.data.errors
+      .map((issue) => `${issue.field}: ${issue.message}`)
+      .join('; ');
+  }
+  if (error?.type === 'failed') {
+    return error.data;
+  }
+  return String(e);
+}
+
 export const usePreferencesStore = defineStore('preferences', {
   state: () => ({
     leafPreferences: {} as LeafPreferences,
@@ -89,8 +107,8 @@ export const usePreferencesStore = defineStore('preferences', {
     },
     async updateLeafPreferences(
       preferences: UpdateLeafPreferences
-    ): Promise<void> {
-      await invoke('set_preferences', {
+    ): Promise<PreferencesUpdate> {
+      const update = await invoke<PreferencesUpdate>('set_preferences', {
         preferences: JSON.stringify({
           ...preferences,
           log_level: Number(preferences.log_level),
@@ -98,6 +116,7 @@ export const usePreferencesStore = defineStore('preferences', {
         }),
       });
       await this.fetchLeafPreferences();
+      return update;
     },
   },
 });
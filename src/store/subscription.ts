@@ -57,14 +57,33 @@ export const useSubscriptionStore = defineStore('subscription', {
 
     async updateSubscription(clientId: string) {
       this.subscriptionState = SubscriptionState.Fetching;
+      this.subscriptionError = '';
+
+      try {
+        await invoke('update_subscription', { clientId: clientId });
+      } catch (e) {
+        this.subscriptionState = SubscriptionState.Error;
+        this.subscriptionError = String(e);
+      }
+    },
+
+    // Reads the QR code from the clipboard when no path is given.
+    async importQrCode(path: string | null): Promise<string> {
+      this.subscriptionState = SubscriptionState.Fetching;
+      this.subscriptionError = '';
 
-      await invoke('update_subscription', { clientId: clientId });
+      try {
+        return await invoke<string>('import_qr_code', { path });
+      } catch (e) {
+        this.subscriptionState = SubscriptionState.Error;
+        this.subscriptionError = String(e);
+        throw e;
+      }
     },
 
     async importOfflineSubscription(payload: {
       path: string;
       passphrase: string | null;
-      keyringJson: string;
     }) {
       this.subscriptionState = SubscriptionState.Fetching;
       this.subscriptionError = '';
@@ -72,14 +91,19 @@ export const useSubscriptionStore = defineStore('subscription', {
       await invoke('import_offline_subscription', {
         path: payload.path,
         passphrase: payload.passphrase,
-        keyringJson: payload.keyringJson,
       });
     },
 
     async autoUpdateSubscription() {
       this.subscriptionState = SubscriptionState.Fetching;
+      this.subscriptionError = '';
 
-      await invoke('auto_update_subscription');
+      try {
+        await invoke('auto_update_subscription');
+      } catch (e) {
+        this.subscriptionState = SubscriptionState.Error;
+        this.subscriptionError = String(e);
+      }
     },
 
     async dispose(): Promise<void> {
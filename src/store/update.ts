@@ -3,6 +3,7 @@ import {
   FileWatchEvent,
   LinuxSystemInfo,
   LinuxUpdateResponse,
+  PolicyStatus,
   UpdateInfo,
   UpdateProgress,
   UpdateState,
@@ -102,6 +103,12 @@ export const useUpdateStore = defineStore('update', {
         return;
       }
 
+      const policy = await invoke<PolicyStatus>('get_policy');
+      if (!policy.policy.app_updates) {
+        this.updateState = UpdateState.NotAvailable;
+        return;
+      }
+
       this.updateState = UpdateState.Checking;
       this.updateError = '';
       this.updateInfo = null;
@@ -20,6 +20,7 @@ export interface LeafPreferences {
   bypass_geosite_list?: string[];
   reject_geoip_list?: string[];
   reject_geosite_list?: string[];
+  locked_fields?: string[];
 }
 
 export interface UpdateLeafPreferences {
@@ -41,6 +42,51 @@ export interface UpdateLeafPreferences {
   reject_geosite_list?: string[];
 }
 
+export interface FieldIssue {
+  field: string;
+  message: string;
+}
+
+export interface ValidationReport {
+  errors: FieldIssue[];
+  warnings: FieldIssue[];
+}
+
+export type PreferencesError =
+  | { type: 'invalid'; data: ValidationReport }
+  | { type: 'failed'; data: string };
+
+export type ChangeEffect = 'immediate' | 'reload' | 'restart';
+
+export interface PreferenceChange {
+  field: string;
+  old: unknown;
+  new: unknown;
+  effect: ChangeEffect;
+}
+
+export interface PreferencesUpdate {
+  report: ValidationReport;
+  changes: PreferenceChange[];
+  reloaded: boolean;
+  restart_required: boolean;
+}
+
+export type UpdateMode = 'automatic' | 'manual' | 'disabled';
+
+export interface PolicyStatus {
+  path: string;
+  loaded: boolean;
+  error?: string;
+  policy: {
+    preferences: Record<string, unknown>;
+    client_id?: string;
+    disabled_commands: string[];
+    update_mode: UpdateMode;
+    app_updates: boolean;
+  };
+}
+
 export enum CoreState {
   Stopped = 'Stopped',
   Started = 'Started',
@@ -122,10 +168,23 @@ export type LeafEvent =
   | { type: 'reloaded' }
   | { type: 'error'; data: { error: string } };
 
+export type FetchSetting = 'auto' | 'on' | 'off';
+
+export interface FetchOptions {
+  tls: FetchSetting;
+  fragment: FetchSetting;
+}
+
+// `null` marks an option left to auto-selection, whose choice is not reported.
+export interface EffectiveFetchOptions {
+  tls: boolean | null;
+  fragment: boolean | null;
+}
+
 export type SubscriptionEvent =
   | { type: 'updating' }
-  | { type: 'success' }
-  | { type: 'error'; data: { error: string } };
+  | { type: 'success'; fetch_options?: EffectiveFetchOptions }
+  | { type: 'error'; data: { error: string }; fetch_options?: EffectiveFetchOptions };
 
 export interface LinuxSystemInfo {
   packageManagerType: string;
@@ -144,3 +203,52 @@ export interface FileWatchEvent {
   path: string;
   eventType: string;
 }
+
+export interface OutboundRename {
+  from: string;
+  to: string;
+}
+
+export interface SelectChange {
+  group: string;
+  added: string[];
+  removed: string[];
+}
+
+export interface SubscriptionDiff {
+  timestamp: number;
+  subscription: string | null;
+  outbounds_added: string[];
+  outbounds_removed: string[];
+  outbounds_renamed: OutboundRename[];
+  select_changes: SelectChange[];
+  rules_added: Record<string, unknown>[];
+  rules_removed: Record<string, unknown>[];
+}
+
+export type DeepLinkAction =
+  | { type: 'install'; profile: string }
+  | { type: 'connect' }
+  | { type: 'disconnect' }
+  | { type: 'select'; outbound: string }
+  | { type: 'import'; url: string };
+
+export interface DeepLinkEvent {
+  url: string;
+  action: DeepLinkAction | null;
+  signed_by: string | null;
+  error: string | null;
+}
+
+export type Intent =
+  | { type: 'open_file'; path: string }
+  | { type: 'deep_link'; url: string }
+  | { type: 'command'; action: DeepLinkAction };
+
+export interface ShareQrCode {
+  link: string;
+  png: string;
+  warning: string;
+  expires_at: number | null;
+  public_key: string | null;
+}